@@ -0,0 +1,63 @@
+//! Runs a chat server inside another program, adds a command, filters its messages with a hook and stops it again.
+//! `cargo run --example embedded_server`
+
+use std::{thread, time::Duration};
+
+use azeezd_sockets::socket_chat::{ChatClient, ChatEvent, Command, Server, ServerHooks};
+
+/// Replaces a word in public messages and turns `/announce` off
+struct Filter;
+
+impl ServerHooks for Filter {
+    fn on_message(&self, _sender: &str, message: &mut String) -> Result<(), String> {
+        *message = message.replace("darn", "****");
+        Ok(())
+    }
+
+    fn on_command(&self, _sender: &str, command: &mut String) -> Result<(), String> {
+        if command.starts_with("/announce") {
+            return Err("Announcements are disabled here".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn main() {
+    let mut server = Server::builder()
+        .address("127.0.0.1:0")
+        .max_clients(8)
+        .max_message_length(256)
+        .command(Command::new("/ping", "Answers with pong", |context, _| {
+            context.reply("pong");
+            Ok(())
+        }))
+        .hook(Filter)
+        .build()
+        .expect("Error creating server");
+    let handle = server.handle();
+    let running = thread::spawn(move || server.init());
+
+    let address = handle.address().to_string();
+    let mut client = ChatClient::connect(&address, "example").expect("Error connecting");
+    client.send_message("well darn").expect("Error sending");
+    client.command("/announce hello").expect("Error sending");
+    client.command("/ping").expect("Error sending");
+    client.command("/help").expect("Error sending");
+    client.command("/help w").expect("Error sending");
+    client.command("/w").expect("Error sending");
+    client.command("/nope").expect("Error sending");
+
+    thread::sleep(Duration::from_millis(200));
+    for event in client.poll_events() {
+        println!("{:?}", event);
+    }
+
+    handle.stop();
+    running.join().expect("Server thread panicked");
+    for event in client.events() {
+        println!("{:?}", event);
+        if event == ChatEvent::Closed {
+            break;
+        }
+    }
+}
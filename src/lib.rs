@@ -0,0 +1,9 @@
+//! A terminal chat server and client over TCP.
+//! `socket_chat::ChatClient` can be used to talk to a server from code
+
+pub mod socket_chat;
+
+/// The README's examples, compiled by `cargo test` so they keep up with the API
+#[cfg(doctest)]
+#[doc = include_str!("../README.md")]
+pub struct ReadmeDoctests;
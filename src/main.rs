@@ -1,15 +1,179 @@
-mod socket_chat;
+use azeezd_sockets::socket_chat;
+
+use std::path::PathBuf;
+
+/// Size a log file may grow to before it is rotated (10 MiB)
+const DEFAULT_LOG_MAX_SIZE: u64 = 10 * 1024 * 1024;
+/// Number of rotated log files to keep
+const DEFAULT_LOG_KEEP: usize = 5;
+/// Environment variable holding the operator password the client joins with. Not a flag so it does not show up in the process list
+const OPERATOR_PASSWORD_VAR: &str = "CHAT_OPERATOR_PASSWORD";
+
+/// # `option_value`
+/// Returns the value following the flag `name` in the arguments, if any. (`--log-level info` gives `Some("info")`)
+fn option_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|i| args.get(i + 1))
+        .map(|value| value.as_str())
+}
+
+/// # `option_values`
+/// Returns the value following every occurrence of the flag `name` (`--address a --address b` gives `["a", "b"]`)
+fn option_values<'a>(args: &'a [String], name: &str) -> Vec<&'a str> {
+    args.windows(2)
+        .filter(|pair| pair[0] == name)
+        .map(|pair| pair[1].as_str())
+        .collect()
+}
+
+/// # `init_server_logging`
+/// Installs the server logger according to the `--log-*` flags
+fn init_server_logging(args: &[String]) {
+    let level = match option_value(args, "--log-level") {
+        Some(level) => socket_chat::parse_level(level).expect("Unknown log level"),
+        None => log::LevelFilter::Debug,
+    };
+
+    let mut logger = socket_chat::ServerLogger::new();
+    if args.contains(&"--log-json".to_string()) {
+        logger = logger.format(socket_chat::LogFormat::Json);
+    }
+    if let Some(path) = option_value(args, "--log-file") {
+        let max_size = option_value(args, "--log-max-size")
+            .map(|size| size.parse().expect("Invalid log file size"))
+            .unwrap_or(DEFAULT_LOG_MAX_SIZE);
+        let keep = option_value(args, "--log-keep")
+            .map(|keep| keep.parse().expect("Invalid number of log files to keep"))
+            .unwrap_or(DEFAULT_LOG_KEEP);
+        logger = logger
+            .file(PathBuf::from(path), max_size, keep)
+            .expect("Error opening log file");
+    }
+
+    logger.install(level).expect("Error installing logger");
+}
+
+/// # `audit_file`
+/// Path of the audit file, `--audit-file` or the default
+fn audit_file(args: &[String]) -> PathBuf {
+    PathBuf::from(option_value(args, "--audit-file").unwrap_or(socket_chat::DEFAULT_AUDIT_FILE))
+}
+
+/// # `query_audit_log`
+/// `audit` subcommand. Prints the audit records matching `--user`, `--ip`, `--since` and `--until`
+fn query_audit_log(args: &[String]) {
+    let query = socket_chat::AuditQuery {
+        user: option_value(args, "--user").map(|user| user.to_string()),
+        ip: option_value(args, "--ip").map(|ip| ip.parse().expect("Invalid IP address")),
+        since: option_value(args, "--since").map(|time| socket_chat::parse_time(time).expect("Invalid --since time")),
+        until: option_value(args, "--until").map(|time| socket_chat::parse_time(time).expect("Invalid --until time")),
+    };
+
+    for record in query.run(&audit_file(args)).expect("Error reading audit log") {
+        println!("{}", serde_json::to_string(&record).expect("Error formatting audit record"));
+    }
+}
+
+/// # `admin_socket`
+/// Path of the admin socket, `--admin-socket` or the default
+fn admin_socket(args: &[String]) -> PathBuf {
+    PathBuf::from(option_value(args, "--admin-socket").unwrap_or(socket_chat::DEFAULT_ADMIN_SOCKET))
+}
+
+/// # `run_admin_command`
+/// `admin` subcommand. Sends everything after `admin` (minus `--admin-socket <path>`) to the server's admin socket and prints the reply
+fn run_admin_command(args: &[String]) {
+    let mut command = Vec::new();
+    let mut rest = args[2..].iter();
+    while let Some(arg) = rest.next() {
+        if arg == "--admin-socket" {
+            rest.next();
+        } else {
+            command.push(arg.as_str());
+        }
+    }
+    if command.is_empty() {
+        eprintln!("Usage: admin [--admin-socket <path>] <LIST | KICK name | BAN name/ip | UNBAN name/ip | NOTICE text | RELOAD | SHUTDOWN>");
+        std::process::exit(2);
+    }
+
+    let reply = socket_chat::send_admin_command(&admin_socket(args), &command.join(" "))
+        .expect("Error talking to the admin socket");
+    for line in reply.iter() {
+        println!("{}", line);
+    }
+    if reply.last().is_none_or(|line| line != "OK") {
+        std::process::exit(1);
+    }
+}
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
 
-    if args.contains(&"-s".to_string()) { // Become server
-        socket_chat::Server::new()
-            .expect("Error creating a new server")
-            .init();
+    if args.get(1).map(|arg| arg.as_str()) == Some("audit") { // Search the audit log
+        query_audit_log(&args);
+    } else if args.get(1).map(|arg| arg.as_str()) == Some("admin") { // Talk to a running server
+        run_admin_command(&args);
+    } else if args.contains(&"-s".to_string()) { // Become server
+        init_server_logging(&args);
+        socket_chat::open_audit_log(&audit_file(&args)).expect("Error opening audit log");
+        if let Some(address) = option_value(&args, "--metrics") {
+            socket_chat::serve_metrics(address).expect("Error starting metrics endpoint");
+        }
+        let addresses = option_values(&args, "--address");
+        let mut server = if addresses.is_empty() {
+            socket_chat::Server::new()
+        } else {
+            socket_chat::Server::bind(&addresses)
+        }
+        .expect("Error creating a new server");
+        if let Some(path) = option_value(&args, "--config") {
+            server.load_config(PathBuf::from(path)).expect("Error loading config");
+        }
+        if let Some(address) = option_value(&args, "--websocket") {
+            server.listen_websocket(address).expect("Error opening WebSocket listener");
+        }
+        if let Some(address) = option_value(&args, "--irc") {
+            server.listen_irc(address).expect("Error opening IRC listener");
+        }
+        if let Some(address) = option_value(&args, "--http") {
+            server.listen_http(address).expect("Error opening HTTP API listener");
+        }
+        match option_value(&args, "--bot") {
+            Some("dice") => server.add_bot(socket_chat::DiceBot::new()),
+            Some(bot) => panic!("Unknown bot \"{}\"", bot),
+            None => {}
+        }
+        if args.contains(&"--admin".to_string()) || args.contains(&"--admin-socket".to_string()) {
+            server.serve_admin(&admin_socket(&args)).expect("Error opening admin socket");
+        }
+        server.init();
     } else { // Become client
-        socket_chat::Client::new(args.get(1).expect("No Name Given").to_string())
-            .expect("Error connecting to server")
-            .init();
+        // The name is the first argument that is neither a flag nor the value of `--address`
+        let name = args[1..]
+            .iter()
+            .zip(args.iter())
+            .find(|(arg, previous)| !arg.starts_with('-') && *previous != "--address")
+            .map(|(arg, _)| arg)
+            .expect("No Name Given");
+        let address = option_value(&args, "--address").unwrap_or(socket_chat::SERVER_ADDRESS);
+        let bell = args.contains(&"--bell".to_string());
+        let password = std::env::var(OPERATOR_PASSWORD_VAR).ok();
+        if args.contains(&"--tui".to_string()) {
+            let client = match password {
+                Some(password) => socket_chat::ChatClient::connect_operator(address, name, &password),
+                None => socket_chat::ChatClient::connect(address, name),
+            }
+            .expect("Error connecting to server");
+            socket_chat::Tui::new(client).bell(bell).init().expect("Error running terminal UI");
+        } else {
+            let client = match password {
+                Some(password) => socket_chat::Client::connect_operator(address, name.to_string(), &password),
+                None => socket_chat::Client::connect(address, name.to_string()),
+            };
+            let mut client = client.expect("Error connecting to server").bell(bell);
+            client.init();
+        }
     }
 }
@@ -0,0 +1,241 @@
+use std::{
+    fs::{self, Permissions},
+    io::{self, BufRead, BufReader, Write},
+    net::IpAddr,
+    os::unix::{fs::PermissionsExt, net::UnixStream},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+};
+
+use super::hooks::HookList;
+use super::server::ServerHandle;
+use super::tools::{audit, metered_lock, AuditEvent, BanList, Config};
+use super::transport::bind_unix;
+use super::{server_log, ClientList, LogMessagType, Server};
+
+/// Where the admin socket is created when `--admin` is given without a path
+pub const DEFAULT_ADMIN_SOCKET: &str = "/tmp/azeezd-sockets.sock";
+
+/// Last line of a successful reply
+const REPLY_OK: &str = "OK";
+/// Start of the last line of a failed reply, followed by the reason
+const REPLY_ERROR: &str = "ERR";
+
+/// # `AdminContext`
+/// The parts of a running `Server` the admin socket works on
+#[derive(Clone)]
+pub struct AdminContext {
+    pub clients: Arc<Mutex<ClientList>>,
+    pub bans: Arc<Mutex<BanList>>,
+    pub config: Arc<Mutex<Config>>,
+    pub config_path: Option<PathBuf>,
+    pub hooks: HookList,
+    /// Stops the server on `SHUTDOWN`, and the admin socket along with it
+    pub handle: ServerHandle,
+}
+
+/// # `serve_admin`
+/// Binds a Unix domain socket at `path` (readable and writable by the owner only) and answers admin commands on it from a background thread.
+/// A stale socket file from an earlier run is replaced, but not one a running server still listens on. The socket is removed once the server stops
+pub fn serve_admin(path: &Path, context: AdminContext) -> io::Result<()> {
+    let listener = bind_unix(path)?;
+    fs::set_permissions(path, Permissions::from_mode(0o600))?;
+    server_log(format!("Admin socket listening on {}", path.display()), LogMessagType::Information);
+
+    let path = path.to_path_buf();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if !context.handle.is_running() {
+                break;
+            }
+            let context = context.clone();
+            thread::spawn(move || context.handle_connection(stream));
+        }
+        let _ = fs::remove_file(&path);
+    });
+    Ok(())
+}
+
+/// # `send_admin_command`
+/// Sends one command line to the admin socket at `path` and returns the reply lines, the last one being `OK` or `ERR <reason>`
+pub fn send_admin_command(path: &Path, command: &str) -> io::Result<Vec<String>> {
+    let mut stream = UnixStream::connect(path)?;
+    writeln!(stream, "{}", command)?;
+
+    let mut reply = Vec::new();
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        let done = line == REPLY_OK || line.starts_with(REPLY_ERROR);
+        reply.push(line);
+        if done {
+            break;
+        }
+    }
+    Ok(reply)
+}
+
+impl AdminContext {
+    /// # `handle_connection`
+    /// Answers command lines from one admin connection until it closes
+    fn handle_connection(&self, stream: UnixStream) {
+        let mut writer = match stream.try_clone() {
+            Ok(val) => val,
+            Err(e) => {
+                server_log(format!("Error \"{}\" cloning admin connection", e), LogMessagType::EncounteredError);
+                return;
+            }
+        };
+
+        for line in BufReader::new(stream).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            server_log(format!("Admin command \"{}\"", line.trim()), LogMessagType::Information);
+
+            let reply = match self.run(line.trim()) {
+                Ok(mut lines) => {
+                    lines.push(REPLY_OK.to_string());
+                    lines
+                }
+                Err(e) => vec![format!("{} {}", REPLY_ERROR, e)],
+            };
+            for reply_line in reply {
+                if writeln!(writer, "{}", reply_line).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// # `run`
+    /// Runs one admin command. Returns the reply lines or the reason it failed
+    fn run(&self, command: &str) -> Result<Vec<String>, String> {
+        let (name, argument) = match command.split_once(' ') {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (command, ""),
+        };
+
+        match (name.to_ascii_uppercase().as_str(), argument.is_empty()) {
+            ("LIST", _) => self.list(),
+            ("KICK", false) => self.kick(argument).map(|_| Vec::new()),
+            ("BAN", false) => self.ban(argument).map(|_| Vec::new()),
+            ("UNBAN", false) => self.unban(argument).map(|_| Vec::new()),
+            ("NOTICE", false) => self.notice(argument).map(|_| Vec::new()),
+            ("RELOAD", _) => self.reload().map(|_| Vec::new()),
+            ("SHUTDOWN", _) => self.shutdown().map(|_| Vec::new()),
+            ("KICK" | "BAN" | "UNBAN" | "NOTICE", true) => Err(format!("{} needs an argument", name)),
+            _ => Err(format!("Unknown command \"{}\"", name)),
+        }
+    }
+
+    fn lock_clients(&self) -> Result<MutexGuard<'_, ClientList>, String> {
+        metered_lock(&self.clients).map_err(|e| format!("Error \"{}\" acquiring client list", e))
+    }
+
+    /// # `list`
+    /// One line per client: name, address and connection time
+    fn list(&self) -> Result<Vec<String>, String> {
+        Ok(self
+            .lock_clients()?
+            .list()
+            .into_iter()
+            .map(|client| {
+                format!(
+                    "{} {} {} {} idle {}s",
+                    client.name,
+                    client.address.map(|address| address.to_string()).unwrap_or_else(|| "-".to_string()),
+                    client.connected_at.format("%Y-%m-%d %H:%M:%S"),
+                    client.presence.state(),
+                    client.idle.as_secs()
+                )
+            })
+            .collect())
+    }
+
+    /// # `kick`
+    /// Disconnects a client and tells everyone else it left
+    fn kick(&self, name: &str) -> Result<(), String> {
+        let mut clients = self.lock_clients()?;
+        self.kick_locked(&mut clients, name, "Kicked by admin")
+    }
+
+    fn kick_locked(&self, clients: &mut ClientList, name: &str, reason: &str) -> Result<(), String> {
+        let name = name.to_string();
+        let address = clients.address_of(&name);
+        clients.kick(&name).map_err(|e| e.to_string())?;
+        audit(AuditEvent::Kick, Some(&name), address.map(|address| address.to_string()), Some(reason.to_string()));
+        clients.send_to_all(&format!("{} {}", super::resposne_type::USER_LEFT, name));
+        for hook in self.hooks.iter() {
+            hook.on_disconnect(&name);
+        }
+        Ok(())
+    }
+
+    /// # `ban`
+    /// Bans a name or an IP address and kicks whoever is connected under it
+    fn ban(&self, target: &str) -> Result<(), String> {
+        // Clients first, the way the dispatcher locks them (`/nick` checks the bans with the client list held)
+        let mut clients = self.lock_clients()?;
+        let mut bans = self.bans.lock().map_err(|e| format!("Error \"{}\" acquiring ban list", e))?;
+
+        let kicked = match target.parse::<IpAddr>() {
+            Ok(address) => {
+                bans.ban_address(address);
+                audit(AuditEvent::Ban, None, Some(address.to_string()), None);
+                clients.names_from(address)
+            }
+            Err(_) => {
+                bans.ban_name(target.to_string());
+                audit(AuditEvent::Ban, Some(target), None, None);
+                vec![target.to_string()]
+            }
+        };
+
+        for name in kicked {
+            let _ = self.kick_locked(&mut clients, &name, "Banned by admin");
+        }
+        Ok(())
+    }
+
+    /// # `unban`
+    /// Lifts the ban of a name or an IP address
+    fn unban(&self, target: &str) -> Result<(), String> {
+        let mut bans = self.bans.lock().map_err(|e| format!("Error \"{}\" acquiring ban list", e))?;
+        bans.unban(target).map_err(|e| e.to_string())?;
+        match target.parse::<IpAddr>() {
+            Ok(address) => audit(AuditEvent::Unban, None, Some(address.to_string()), None),
+            Err(_) => audit(AuditEvent::Unban, Some(target), None, None),
+        }
+        Ok(())
+    }
+
+    /// # `notice`
+    /// Sends a system message to everyone
+    fn notice(&self, message: &str) -> Result<(), String> {
+        self.lock_clients()?
+            .send_to_all(&format!("{} {}", super::resposne_type::SYSTEM, message));
+        Ok(())
+    }
+
+    /// # `reload`
+    /// Reads the config file again and applies it
+    fn reload(&self) -> Result<(), String> {
+        let path = self.config_path.as_ref().ok_or("Server was started without a config file")?;
+        Server::apply_config(Config::load(path)?, &self.config, &self.bans);
+        Ok(())
+    }
+
+    /// # `shutdown`
+    /// Stops the server the same way `ServerHandle::stop` does: `Server::init` disconnects every client and returns.
+    /// Whatever runs the server decides what happens next, the binary exits
+    fn shutdown(&self) -> Result<(), String> {
+        server_log("Shutting down on admin request".to_string(), LogMessagType::Information);
+        self.handle.stop();
+        Ok(())
+    }
+}
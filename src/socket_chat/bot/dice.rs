@@ -0,0 +1,200 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{Bot, BotContext, BotEvent};
+
+/// What a message has to start with for the dice bot to roll
+const ROLL: &str = "!roll";
+/// Most dice one roll may throw
+const MAX_DICE: u64 = 100;
+/// Most sides a die may have
+const MAX_SIDES: u64 = 1000;
+
+/// # `DiceBot`
+/// Rolls dice. `!roll` throws a six-sided die, `!roll 2d20` two twenty-sided ones.
+/// Public rolls are answered in public, whispered rolls with a whisper. Anything else is ignored, whispers included
+pub struct DiceBot {
+    name: String,
+    state: u64,
+}
+
+impl DiceBot {
+    /// # `new`
+    /// Creates the bot under the name `dice`
+    pub fn new() -> DiceBot {
+        DiceBot::named("dice")
+    }
+
+    /// # `named`
+    /// Creates the bot under `name`
+    pub fn named(name: &str) -> DiceBot {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos() as u64)
+            .unwrap_or_default();
+        DiceBot {
+            name: name.to_string(),
+            state: seed | 1, // xorshift must not start at 0
+        }
+    }
+
+    /// # `next`
+    /// Next pseudo-random number (xorshift64), good enough for dice
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// # `command`
+    /// What follows `!roll` when `message` is a roll, `None` for anything else
+    fn command(message: &str) -> Option<&str> {
+        let (word, dice) = message.split_once(' ').unwrap_or((message, ""));
+        (word == ROLL).then(|| dice.trim())
+    }
+
+    /// # `roll`
+    /// Takes what follows `!roll` (`2d20`, empty for `1d6`) and returns the answer
+    fn roll(&mut self, sender: &str, dice: &str) -> String {
+        let dice = if dice.is_empty() { "1d6" } else { dice };
+        let parsed = dice
+            .split_once('d')
+            .and_then(|(count, sides)| Some((if count.is_empty() { 1 } else { count.parse::<u64>().ok()? }, sides.parse::<u64>().ok()?)));
+
+        match parsed {
+            Some((count, sides)) if (1..=MAX_DICE).contains(&count) && (2..=MAX_SIDES).contains(&sides) => {
+                let rolls: Vec<u64> = (0..count).map(|_| self.next() % sides + 1).collect();
+                let total: u64 = rolls.iter().sum();
+                let rolls: Vec<String> = rolls.iter().map(|roll| roll.to_string()).collect();
+                format!("{} rolled {}: {} (total {})", sender, dice, rolls.join(" "), total)
+            }
+            _ => format!(
+                "Usage: {} [<count>d<sides>], up to {} dice with 2 to {} sides (like {} 2d20)",
+                ROLL, MAX_DICE, MAX_SIDES, ROLL
+            ),
+        }
+    }
+}
+
+impl Default for DiceBot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bot for DiceBot {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn on_event(&mut self, event: BotEvent, context: &BotContext) {
+        match event {
+            BotEvent::Message { sender, message } => {
+                if let Some(dice) = DiceBot::command(&message) {
+                    let answer = self.roll(&sender, dice);
+                    context.say(&answer);
+                }
+            }
+            BotEvent::Whisper { sender, message } => {
+                if let Some(dice) = DiceBot::command(&message) {
+                    let answer = self.roll(&sender, dice);
+                    context.whisper(&sender, &answer);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    fn bot() -> DiceBot {
+        DiceBot {
+            name: "dice".to_string(),
+            state: 0x2545_f491_4f6c_dd1d,
+        }
+    }
+
+    /// The single rolls and the total of an answer
+    fn rolls(answer: &str) -> (Vec<u64>, u64) {
+        let (rolls, total) = answer.split_once(": ").unwrap().1.split_once(" (total ").unwrap();
+        (rolls.split(' ').map(|roll| roll.parse().unwrap()).collect(), total.trim_end_matches(')').parse().unwrap())
+    }
+
+    fn is_usage(answer: &str) -> bool {
+        answer.starts_with("Usage: !roll")
+    }
+
+    #[test]
+    fn rolls_a_six_sided_die_by_default() {
+        let answer = bot().roll("alice", "");
+        assert!(answer.starts_with("alice rolled 1d6: "), "{}", answer);
+        let (rolls, total) = rolls(&answer);
+        assert_eq!(rolls.len(), 1);
+        assert!((1..=6).contains(&total));
+    }
+
+    #[test]
+    fn rolls_count_dice_with_the_given_sides() {
+        let mut bot = bot();
+        for _ in 0..50 {
+            let (rolls, total) = rolls(&bot.roll("alice", "3d20"));
+            assert_eq!(rolls.len(), 3);
+            assert!(rolls.iter().all(|roll| (1..=20).contains(roll)));
+            assert_eq!(rolls.iter().sum::<u64>(), total);
+        }
+        assert_eq!(rolls(&bot.roll("alice", "d8")).0.len(), 1);
+    }
+
+    #[test]
+    fn keeps_to_the_limits() {
+        let mut bot = bot();
+        assert_eq!(rolls(&bot.roll("alice", "100d6")).0.len(), 100);
+        assert!(!is_usage(&bot.roll("alice", "1d2")));
+        assert!(!is_usage(&bot.roll("alice", "1d1000")));
+        for dice in ["101d6", "0d6", "1d1", "1d0", "1d1001", "99999999999999999999d6"] {
+            assert!(is_usage(&bot.roll("alice", dice)), "{}", dice);
+        }
+    }
+
+    #[test]
+    fn answers_malformed_dice_with_the_usage() {
+        let mut bot = bot();
+        for dice in ["abc", "2x6", "d", "2d", "-1d6", "2d-6", "2d6d6", "2 d6", "1.5d6"] {
+            assert!(is_usage(&bot.roll("alice", dice)), "{}", dice);
+        }
+    }
+
+    #[test]
+    fn only_roll_commands_are_commands() {
+        assert_eq!(DiceBot::command("!roll"), Some(""));
+        assert_eq!(DiceBot::command("!roll  2d20 "), Some("2d20"));
+        assert_eq!(DiceBot::command("!rolling 2d20"), None);
+        assert_eq!(DiceBot::command("2d20"), None);
+        assert_eq!(DiceBot::command("Usage: !roll [<count>d<sides>]"), None);
+    }
+
+    #[test]
+    fn ignores_whispers_that_are_not_rolls() {
+        let (requests, sent) = mpsc::channel();
+        let context = BotContext {
+            name: "dice".to_string(),
+            requests,
+        };
+        let mut bot = bot();
+        let whisper = |message: &str| BotEvent::Whisper {
+            sender: "alice".to_string(),
+            message: message.to_string(),
+        };
+
+        bot.on_event(whisper("hello"), &context);
+        bot.on_event(whisper("Usage: !roll [<count>d<sides>]"), &context);
+        assert!(sent.try_recv().is_err());
+
+        bot.on_event(whisper("!roll 2d6"), &context);
+        assert!(sent.try_recv().unwrap().starts_with("CMD dice /w alice alice rolled 2d6: "));
+    }
+}
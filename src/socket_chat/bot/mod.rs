@@ -0,0 +1,93 @@
+mod dice;
+
+use std::{
+    collections::HashSet,
+    sync::{
+        mpsc::{Receiver, Sender},
+        Arc,
+    },
+    thread,
+};
+
+use super::chat_client::ChatEvent;
+use super::{commands, request_type, server_log, LogMessagType};
+
+pub use dice::DiceBot;
+
+/// # `BotEvent`
+/// Something a bot is told about
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BotEvent {
+    /// A public message from someone else
+    Message { sender: String, message: String },
+    /// A whisper to the bot
+    Whisper { sender: String, message: String },
+}
+
+/// # `Bot`
+/// An automated user the server hosts in-process. It joins under its own name like any client, gets public messages
+/// and whispers as `BotEvent`s on its own thread and answers through its `BotContext`
+pub trait Bot: Send {
+    /// # `name`
+    /// The name the bot joins with. It is taken before the server accepts anyone
+    fn name(&self) -> &str;
+
+    /// # `on_event`
+    /// Called for every public message and whisper the bot gets, except its own messages and those of other bots the
+    /// server hosts
+    fn on_event(&mut self, event: BotEvent, context: &BotContext);
+}
+
+/// # `BotContext`
+/// Lets a bot talk. Its messages go through the server's request handling like those of any client, hooks and limits included
+pub struct BotContext {
+    name: String,
+    requests: Sender<String>,
+}
+
+impl BotContext {
+    /// # `name`
+    /// The bot's name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// # `say`
+    /// Sends a public message
+    pub fn say(&self, message: &str) {
+        self.request(request_type::MESSAGE, message);
+    }
+
+    /// # `whisper`
+    /// Sends a private message to `target`
+    pub fn whisper(&self, target: &str, message: &str) {
+        self.request(request_type::COMMAND, &format!("{} {} {}", commands::WHISPER, target, message));
+    }
+
+    fn request(&self, identifier: &str, content: &str) {
+        if self.requests.send(format!("{} {} {}", identifier, self.name, content)).is_err() {
+            server_log(format!("Bot {} could not send, the server has stopped", self.name), LogMessagType::EncounteredError);
+        }
+    }
+}
+
+/// # `run_bot`
+/// Starts the thread of a bot. `responses` gets what the server sends the bot, `requests` leads to the server's request handling.
+/// `bots` holds the names of all hosted bots, the bot hears none of them so two bots never answer each other forever.
+/// The thread ends when the bot is removed from the client list or the server stops
+pub(crate) fn run_bot(mut bot: Box<dyn Bot>, responses: Receiver<String>, requests: Sender<String>, bots: Arc<HashSet<String>>) {
+    let context = BotContext {
+        name: bot.name().to_string(),
+        requests,
+    };
+    thread::spawn(move || {
+        for response in responses {
+            let event = match ChatEvent::parse(&response) {
+                ChatEvent::PublicMessage { sender, message, .. } if !bots.contains(&sender) => BotEvent::Message { sender, message },
+                ChatEvent::PrivateMessage { sender, message, .. } if !bots.contains(&sender) => BotEvent::Whisper { sender, message },
+                _ => continue,
+            };
+            bot.on_event(event, &context);
+        }
+    });
+}
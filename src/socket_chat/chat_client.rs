@@ -0,0 +1,716 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    io::{self, BufRead, BufReader, Write},
+    sync::{
+        mpsc::{self, Receiver},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use chrono::{DateTime, Utc};
+
+use super::tools::Presence;
+use super::transport::Stream;
+use super::{commands, request_type, resposne_type};
+
+/// How often `ChatClient::typing` repeats the typing indicator while the user keeps writing
+pub const TYPING_INTERVAL: Duration = Duration::from_secs(3);
+/// How long a typing indicator lasts without being repeated, in case the stop got lost
+pub const TYPING_TIMEOUT: Duration = Duration::from_secs(8);
+
+/// # `ChatEvent`
+/// Something the server told the client, parsed from its response line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChatEvent {
+    /// A public message with the id and time the server gave it. `reply_to` is the id of the message it replies to
+    PublicMessage { id: u64, time: DateTime<Utc>, reply_to: Option<u64>, sender: String, message: String },
+    /// A whisper to this client, stamped like public messages
+    PrivateMessage { id: u64, time: DateTime<Utc>, sender: String, message: String },
+    /// The public message `id` of `sender` was edited and now reads `message`
+    MessageEdited { id: u64, sender: String, message: String },
+    /// The public message with this id was deleted
+    MessageDeleted(u64),
+    /// `user` reacted to the public message `id` with `emoji`
+    ReactionAdded { id: u64, user: String, emoji: String },
+    /// `user` took their `emoji` reaction to the public message `id` back
+    ReactionRemoved { id: u64, user: String, emoji: String },
+    /// One message of a thread asked for with `/thread`, nested `depth` replies deep. They arrive in thread order
+    ThreadMessage { depth: usize, id: u64, time: DateTime<Utc>, sender: String, message: String },
+    /// The public message `id` mentions this client. Comes right after the message, or when joining for mentions made while offline
+    Mentioned { id: u64, time: DateTime<Utc>, sender: String, message: String },
+    UserJoined(String),
+    UserLeft(String),
+    /// A message from the server itself (message of the day, announcements, notices and replies to commands)
+    System(String),
+    /// `name` started (`started` is true) or stopped writing a message to everyone or to this client
+    Typing { name: String, started: bool },
+    /// `old` is now called `new`, after a `/nick`
+    NickChanged { old: String, new: String },
+    /// Everyone that is online, the answer to `/who`
+    UserList(Vec<String>),
+    /// The presence of `name` changed, or was asked for with `/who`. `idle` is how long ago they last sent something
+    Presence { name: String, presence: Presence, idle: Duration },
+    /// The server did not handle a request of this client, with the reason (unknown command, rejected message ...)
+    Error(String),
+    /// The server handled the request with this id
+    Acknowledged(String),
+    /// The whisper with this id was written to `recipient`
+    Delivered { id: String, recipient: String },
+    /// The server did not handle the request with this id, with the reason
+    Rejected { id: String, reason: String },
+    /// The server disconnected this client (kicked, banned or shut down)
+    Disconnected,
+    /// The connection was closed. This is always the last event
+    Closed,
+    /// A response this version of the client does not understand
+    Unknown(String),
+}
+
+impl ChatEvent {
+    /// # `parse`
+    /// Takes a response line as `&str` (without its newline) and returns the event it stands for
+    pub fn parse(response: &str) -> ChatEvent {
+        // Everything after the resposne id (first three characters)
+        let content = response.get(4..).unwrap_or_default();
+
+        match response.get(..3).unwrap_or_default() {
+            resposne_type::CONNECTION_DENIED => ChatEvent::Disconnected,
+            resposne_type::PUBLIC_MESSAGE => match parse_stamped(content) {
+                Some((id, time, sender, message)) => ChatEvent::PublicMessage { id, time, reply_to: None, sender, message },
+                None => ChatEvent::Unknown(response.to_string()),
+            },
+            resposne_type::PRIVATE_MESSAGE => match parse_stamped(content) {
+                Some((id, time, sender, message)) => ChatEvent::PrivateMessage { id, time, sender, message },
+                None => ChatEvent::Unknown(response.to_string()),
+            },
+            resposne_type::REPLY => {
+                // The parent id sits between the stamp and the sender
+                let mut parts = content.splitn(4, ' ');
+                match (parts.next(), parts.next(), parts.next().and_then(|parent| parent.parse().ok()), parts.next()) {
+                    (Some(id), Some(time), Some(reply_to), Some(rest)) => match parse_stamped(&format!("{} {} {}", id, time, rest)) {
+                        Some((id, time, sender, message)) => ChatEvent::PublicMessage { id, time, reply_to: Some(reply_to), sender, message },
+                        None => ChatEvent::Unknown(response.to_string()),
+                    },
+                    _ => ChatEvent::Unknown(response.to_string()),
+                }
+            }
+            resposne_type::THREAD_MESSAGE => match content.split_once(' ') {
+                Some((depth, stamped)) => match (depth.parse(), parse_stamped(stamped)) {
+                    (Ok(depth), Some((id, time, sender, message))) => ChatEvent::ThreadMessage { depth, id, time, sender, message },
+                    _ => ChatEvent::Unknown(response.to_string()),
+                },
+                None => ChatEvent::Unknown(response.to_string()),
+            },
+            resposne_type::MENTION => match parse_stamped(content) {
+                Some((id, time, sender, message)) => ChatEvent::Mentioned { id, time, sender, message },
+                None => ChatEvent::Unknown(response.to_string()),
+            },
+            resposne_type::TYPING => match content.split_once(' ') {
+                Some((name, request_type::TYPING_START)) => ChatEvent::Typing { name: name.to_string(), started: true },
+                Some((name, request_type::TYPING_STOP)) => ChatEvent::Typing { name: name.to_string(), started: false },
+                _ => ChatEvent::Unknown(response.to_string()),
+            },
+            resposne_type::PRESENCE => {
+                let mut parts = content.splitn(4, ' ');
+                let (name, state, idle) = (parts.next(), parts.next(), parts.next().and_then(|idle| idle.parse().ok()));
+                match (name, state.and_then(|state| Presence::parse(state, parts.next())), idle) {
+                    (Some(name), Some(presence), Some(idle)) => ChatEvent::Presence {
+                        name: name.to_string(),
+                        presence,
+                        idle: Duration::from_secs(idle),
+                    },
+                    _ => ChatEvent::Unknown(response.to_string()),
+                }
+            }
+            resposne_type::MESSAGE_EDITED => {
+                let mut parts = content.splitn(3, ' ');
+                match (parts.next().and_then(|id| id.parse().ok()), parts.next()) {
+                    (Some(id), Some(sender)) => ChatEvent::MessageEdited {
+                        id,
+                        sender: sender.to_string(),
+                        message: parts.next().unwrap_or_default().to_string(),
+                    },
+                    _ => ChatEvent::Unknown(response.to_string()),
+                }
+            }
+            resposne_type::MESSAGE_DELETED => match content.parse() {
+                Ok(id) => ChatEvent::MessageDeleted(id),
+                Err(_) => ChatEvent::Unknown(response.to_string()),
+            },
+            resposne_type::REACTION_ADDED | resposne_type::REACTION_REMOVED => {
+                let mut parts = content.splitn(3, ' ');
+                match (parts.next().and_then(|id| id.parse().ok()), parts.next(), parts.next()) {
+                    (Some(id), Some(user), Some(emoji)) => {
+                        let (user, emoji) = (user.to_string(), emoji.to_string());
+                        if response.starts_with(resposne_type::REACTION_ADDED) {
+                            ChatEvent::ReactionAdded { id, user, emoji }
+                        } else {
+                            ChatEvent::ReactionRemoved { id, user, emoji }
+                        }
+                    }
+                    _ => ChatEvent::Unknown(response.to_string()),
+                }
+            }
+            resposne_type::NICK_CHANGED => match content.split_once(' ') {
+                Some((old, new)) => ChatEvent::NickChanged { old: old.to_string(), new: new.to_string() },
+                None => ChatEvent::Unknown(response.to_string()),
+            },
+            resposne_type::USER_JOINED => ChatEvent::UserJoined(content.to_string()),
+            resposne_type::USER_LEFT => ChatEvent::UserLeft(content.to_string()),
+            resposne_type::SYSTEM => ChatEvent::System(content.to_string()),
+            resposne_type::USER_LIST => {
+                ChatEvent::UserList(content.split_ascii_whitespace().map(|name| name.to_string()).collect())
+            }
+            resposne_type::ERROR => ChatEvent::Error(content.to_string()),
+            resposne_type::ACKNOWLEDGED => ChatEvent::Acknowledged(content.to_string()),
+            resposne_type::DELIVERED => {
+                let (id, recipient) = content.split_once(' ').unwrap_or((content, ""));
+                ChatEvent::Delivered { id: id.to_string(), recipient: recipient.to_string() }
+            }
+            resposne_type::REJECTED => {
+                let (id, reason) = content.split_once(' ').unwrap_or((content, ""));
+                ChatEvent::Rejected { id: id.to_string(), reason: reason.to_string() }
+            }
+            _ => ChatEvent::Unknown(response.to_string()),
+        }
+    }
+}
+
+/// # `Reactions`
+/// The reactions on one message: who reacted with which emoji. Front-ends keep one per message and update it from the reaction events
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Reactions {
+    users: BTreeMap<String, Vec<String>>,
+}
+
+impl Reactions {
+    /// # `update`
+    /// Applies a reaction event to these reactions. Other events are ignored
+    pub fn update(&mut self, event: &ChatEvent) {
+        match event {
+            ChatEvent::ReactionAdded { user, emoji, .. } => {
+                let users = self.users.entry(emoji.clone()).or_default();
+                if !users.contains(user) {
+                    users.push(user.clone());
+                }
+            }
+            ChatEvent::ReactionRemoved { user, emoji, .. } => {
+                if let Some(users) = self.users.get_mut(emoji) {
+                    users.retain(|reacted| reacted != user);
+                    if users.is_empty() {
+                        self.users.remove(emoji);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// # `users`
+    /// Who reacted with `emoji`
+    pub fn users(&self, emoji: &str) -> &[String] {
+        self.users.get(emoji).map(|users| users.as_slice()).unwrap_or_default()
+    }
+
+    /// # `is_empty`
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+
+    /// # `summary`
+    /// Every emoji with how many reacted with it (`👍 3  🎉 1`)
+    pub fn summary(&self) -> String {
+        self.users
+            .iter()
+            .map(|(emoji, users)| format!("{} {}", emoji, users.len()))
+            .collect::<Vec<String>>()
+            .join("  ")
+    }
+}
+
+/// # `TypingUsers`
+/// Who is writing a message right now. Front-ends update it from the events, an indicator that is not repeated within
+/// `TYPING_TIMEOUT` runs out by itself
+#[derive(Debug, Clone, Default)]
+pub struct TypingUsers {
+    users: HashMap<String, Instant>,
+}
+
+impl TypingUsers {
+    /// # `update`
+    /// Applies an event. A message, leaving or a new name ends the indicator of its sender as well, other events are ignored
+    pub fn update(&mut self, event: &ChatEvent) {
+        match event {
+            ChatEvent::Typing { name, started: true } => {
+                self.users.insert(name.clone(), Instant::now());
+            }
+            ChatEvent::Typing { name, started: false }
+            | ChatEvent::PublicMessage { sender: name, .. }
+            | ChatEvent::PrivateMessage { sender: name, .. }
+            | ChatEvent::NickChanged { old: name, .. }
+            | ChatEvent::UserLeft(name) => {
+                self.users.remove(name);
+            }
+            _ => {}
+        }
+    }
+
+    /// # `names`
+    /// Who is typing, sorted by name
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .users
+            .iter()
+            .filter(|(_, since)| since.elapsed() < TYPING_TIMEOUT)
+            .map(|(name, _)| name.as_str())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// # `summary`
+    /// `alice is typing…`, `alice and bob are typing…` or `4 people are typing…`. `None` if nobody is
+    pub fn summary(&self) -> Option<String> {
+        match self.names().as_slice() {
+            [] => None,
+            [name] => Some(format!("{} is typing…", name)),
+            [first, second] => Some(format!("{} and {} are typing…", first, second)),
+            names => Some(format!("{} people are typing…", names.len())),
+        }
+    }
+}
+
+/// # `describe_idle`
+/// An idle time the way the terminal clients show it (`42s`, `5m`, `3h`)
+pub(crate) fn describe_idle(idle: Duration) -> String {
+    match idle.as_secs() {
+        seconds if seconds < 60 => format!("{}s", seconds),
+        seconds if seconds < 60 * 60 => format!("{}m", seconds / 60),
+        seconds => format!("{}h", seconds / (60 * 60)),
+    }
+}
+
+/// # `parse_stamped`
+/// Splits `<id> <time> <sender> <text>` of a stamped message
+fn parse_stamped(content: &str) -> Option<(u64, DateTime<Utc>, String, String)> {
+    let mut parts = content.splitn(4, ' ');
+    let id = parts.next()?.parse().ok()?;
+    let time = DateTime::parse_from_rfc3339(parts.next()?).ok()?.with_timezone(&Utc);
+    let sender = parts.next()?.to_string();
+    let message = parts.next().unwrap_or_default().to_string();
+    Some((id, time, sender, message))
+}
+
+/// # `ChatError`
+/// Why a `ChatClient` could not connect or send
+#[derive(Debug)]
+pub enum ChatError {
+    /// Talking to the server failed
+    Io(io::Error),
+    /// The server refused the connection, usually because the name is taken or banned
+    Denied,
+    /// The server answered the connection request with something unexpected
+    UnexpectedResponse(String),
+}
+
+impl fmt::Display for ChatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChatError::Io(e) => write!(f, "Error talking to server: {}", e),
+            ChatError::Denied => write!(f, "Connection Denied"),
+            ChatError::UnexpectedResponse(response) => write!(f, "Unexpected response \"{}\" from server", response),
+        }
+    }
+}
+
+impl std::error::Error for ChatError {}
+
+impl From<io::Error> for ChatError {
+    fn from(e: io::Error) -> Self {
+        ChatError::Io(e)
+    }
+}
+
+/// # `PendingRequest`
+/// A request the server has not acknowledged yet
+#[derive(Debug, Clone)]
+pub struct PendingRequest {
+    /// The id it was sent with
+    pub id: String,
+    /// The message or command line
+    pub content: String,
+    /// When it was first sent
+    pub sent_at: Instant,
+    identifier: String,
+}
+
+/// # `ChatClient`
+/// A connection to a chat server that can be driven from code. Requests are sent with the methods below,
+/// everything the server sends arrives as `ChatEvent`s through `events` or `poll_events`.
+/// Every request but `logout` and typing indicators carries an id, it is pending until the server acknowledges or rejects it and is sent again by `reconnect`.
+/// Front-ends such as the terminal `Client` and the `Tui` are built on this
+pub struct ChatClient {
+    socket: Stream,
+    address: String,
+    /// Shared with the thread reading responses, which follows the renames of this client
+    name: Arc<Mutex<String>>,
+    events: Receiver<ChatEvent>,
+    pending: Arc<Mutex<Vec<PendingRequest>>>,
+    /// Start of every id, tells this client's requests apart from those of an earlier run under the same name
+    session: String,
+    /// The operator password, given again on reconnect
+    password: Option<String>,
+    next_id: u64,
+    /// When the typing indicator was last sent and to whom, while the user is writing
+    typing: Option<(Instant, Option<String>)>,
+}
+
+impl ChatClient {
+    /// # `connect`
+    /// Connects to the server at `address` (`host:port` or `unix:<path>`) and joins as `name`.
+    /// Returns `Err(ChatError::Denied)` if the server refused the name
+    pub fn connect(address: &str, name: &str) -> Result<ChatClient, ChatError> {
+        Self::open(address, name, None)
+    }
+
+    /// # `connect_operator`
+    /// Like `connect` but joins with the operator password, which operator names listed in the server's config need
+    pub fn connect_operator(address: &str, name: &str, password: &str) -> Result<ChatClient, ChatError> {
+        Self::open(address, name, Some(password.to_string()))
+    }
+
+    fn open(address: &str, name: &str, password: Option<String>) -> Result<ChatClient, ChatError> {
+        let pending = Arc::new(Mutex::new(Vec::new()));
+        let name = Arc::new(Mutex::new(name.to_string()));
+        let (socket, events) = Self::join(address, name.clone(), password.as_deref(), pending.clone())?;
+        let session = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| format!("{:x}", time.as_millis()))
+            .unwrap_or_default();
+
+        Ok(ChatClient {
+            socket,
+            address: address.to_string(),
+            name,
+            events,
+            pending,
+            session,
+            password,
+            next_id: 1,
+            typing: None,
+        })
+    }
+
+    /// # `reconnect`
+    /// Connects to the same server again under the current name and sends every pending request again with its id,
+    /// so the server can tell retries from new requests. Returns how many were sent again
+    pub fn reconnect(&mut self) -> Result<usize, ChatError> {
+        let (socket, events) = Self::join(&self.address, self.name.clone(), self.password.as_deref(), self.pending.clone())?;
+        self.socket = socket;
+        self.events = events;
+
+        let pending = self.pending();
+        for request in pending.iter() {
+            self.write(&request.id, &request.identifier, &request.content)?;
+        }
+        Ok(pending.len())
+    }
+
+    /// # `join`
+    /// Opens the connection, sends the connection request and starts the thread that turns responses into events
+    fn join(
+        address: &str,
+        name: Arc<Mutex<String>>,
+        password: Option<&str>,
+        pending: Arc<Mutex<Vec<PendingRequest>>>,
+    ) -> Result<(Stream, Receiver<ChatEvent>), ChatError> {
+        let mut socket = Stream::connect(address)?;
+        let mut reader = BufReader::new(socket.try_clone()?);
+
+        // Send join request to server
+        let joining = name.lock().map(|name| name.clone()).unwrap_or_default();
+        match password {
+            Some(password) => writeln!(socket, "{} {} {}", request_type::CONNECT, joining, password)?,
+            None => writeln!(socket, "{} {}", request_type::CONNECT, joining)?,
+        }
+
+        // Read resposne
+        let mut response = String::new();
+        reader.read_line(&mut response)?;
+        match response.trim_end() {
+            resposne_type::CONNECTION_ACCEPTED => {}
+            resposne_type::CONNECTION_DENIED => return Err(ChatError::Denied),
+            response => return Err(ChatError::UnexpectedResponse(response.to_string())),
+        }
+
+        // Everything after the acceptance is turned into events by a background thread.
+        // Acknowledged and rejected requests stop being pending before their event is seen, and renames of this client are followed
+        let (sender, events) = mpsc::channel::<ChatEvent>();
+        thread::spawn(move || {
+            for line in reader.lines().map_while(Result::ok) {
+                let event = ChatEvent::parse(&line);
+                if let ChatEvent::Acknowledged(id) | ChatEvent::Rejected { id, .. } = &event {
+                    if let Ok(mut pending) = pending.lock() {
+                        pending.retain(|request| request.id != *id);
+                    }
+                }
+                if let ChatEvent::NickChanged { old, new } = &event {
+                    if let Ok(mut name) = name.lock() {
+                        if *name == *old {
+                            *name = new.clone();
+                        }
+                    }
+                }
+                if sender.send(event).is_err() {
+                    return;
+                }
+            }
+            let _ = sender.send(ChatEvent::Closed);
+        });
+
+        Ok((socket, events))
+    }
+
+    /// # `name`
+    /// The name of the client, the one it joined with unless it was changed with `/nick`
+    pub fn name(&self) -> String {
+        self.name.lock().map(|name| name.clone()).unwrap_or_default()
+    }
+
+    /// # `address`
+    /// The address of the server
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// # `pending`
+    /// The requests the server has not acknowledged yet, oldest first
+    pub fn pending(&self) -> Vec<PendingRequest> {
+        self.pending.lock().map(|pending| pending.clone()).unwrap_or_default()
+    }
+
+    /// # `events`
+    /// Blocking iterator over the events from the server. It ends after `ChatEvent::Closed`
+    pub fn events(&self) -> mpsc::Iter<'_, ChatEvent> {
+        self.events.iter()
+    }
+
+    /// # `poll_events`
+    /// Non-blocking iterator over the events that have arrived so far
+    pub fn poll_events(&self) -> mpsc::TryIter<'_, ChatEvent> {
+        self.events.try_iter()
+    }
+
+    /// # `send_message`
+    /// Sends a public message. Returns the id of the request
+    pub fn send_message(&mut self, message: &str) -> Result<String, ChatError> {
+        self.request(request_type::MESSAGE, message)
+    }
+
+    /// # `whisper`
+    /// Sends a private message to `target`. Returns the id of the request, `ChatEvent::Delivered` follows once `target` got it
+    pub fn whisper(&mut self, target: &str, message: &str) -> Result<String, ChatError> {
+        self.request(request_type::COMMAND, &format!("{} {} {}", commands::WHISPER, target, message))
+    }
+
+    /// # `command`
+    /// Sends a command line such as `/who`. The server decides whether it knows the command, unknown ones are rejected.
+    /// Returns the id of the request
+    pub fn command(&mut self, command: &str) -> Result<String, ChatError> {
+        self.request(request_type::COMMAND, command)
+    }
+
+    /// # `logout`
+    /// Leaves the server
+    pub fn logout(&mut self) -> Result<(), ChatError> {
+        writeln!(self.socket, "{} {} {}", request_type::COMMAND, self.name(), commands::LOGOUT)?;
+        Ok(())
+    }
+
+    /// # `typing`
+    /// Tells everyone, or only `target` for a whisper, that the user is writing. Call it on every key press,
+    /// the indicator is only sent again every `TYPING_INTERVAL` or when the target changes
+    pub fn typing(&mut self, target: Option<&str>) -> Result<(), ChatError> {
+        if let Some((sent_at, to)) = &self.typing {
+            if to.as_deref() == target && sent_at.elapsed() < TYPING_INTERVAL {
+                return Ok(());
+            }
+            if to.as_deref() != target {
+                self.stopped_typing()?;
+            }
+        }
+        self.write_typing(request_type::TYPING_START, target)?;
+        self.typing = Some((Instant::now(), target.map(|target| target.to_string())));
+        Ok(())
+    }
+
+    /// # `stopped_typing`
+    /// Takes the typing indicator back, after the message was sent or the input cleared. Does nothing if none was sent
+    pub fn stopped_typing(&mut self) -> Result<(), ChatError> {
+        match self.typing.take() {
+            Some((_, target)) => self.write_typing(request_type::TYPING_STOP, target.as_deref()),
+            None => Ok(()),
+        }
+    }
+
+    /// # `write_typing`
+    /// Writes a typing indicator, without an id since nobody waits for it
+    fn write_typing(&mut self, state: &str, target: Option<&str>) -> Result<(), ChatError> {
+        match target {
+            Some(target) => writeln!(self.socket, "{} {} {} {}", request_type::TYPING, self.name(), state, target)?,
+            None => writeln!(self.socket, "{} {} {}", request_type::TYPING, self.name(), state)?,
+        }
+        Ok(())
+    }
+
+    /// # `send_input`
+    /// Sends a line the way a user would type it: lines starting with `/` are commands, anything else is a public message.
+    /// Returns the id of the request, or `None` for `/exit`
+    pub fn send_input(&mut self, line: &str) -> Result<Option<String>, ChatError> {
+        let line = line.trim_end();
+        if line == commands::LOGOUT {
+            self.logout().map(|_| None)
+        } else if line.starts_with('/') {
+            self.command(line).map(Some)
+        } else {
+            self.send_message(line).map(Some)
+        }
+    }
+
+    /// # `request`
+    /// Gives a request the next id, remembers it as pending and writes it to the server
+    fn request(&mut self, identifier: &str, content: &str) -> Result<String, ChatError> {
+        let id = format!("{}-{}", self.session, self.next_id);
+        self.next_id += 1;
+        let content = content.trim_end().to_string();
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.push(PendingRequest {
+                id: id.clone(),
+                content: content.clone(),
+                sent_at: Instant::now(),
+                identifier: identifier.to_string(),
+            });
+        }
+        self.write(&id, identifier, &content)?;
+        Ok(id)
+    }
+
+    /// # `write`
+    /// Writes a request line (`@<id> <identifier> <name> <content>`) to the server
+    fn write(&mut self, id: &str, identifier: &str, content: &str) -> Result<(), ChatError> {
+        writeln!(self.socket, "{}{} {} {} {}", request_type::ID_PREFIX, id, identifier, self.name(), content)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(stamp: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(stamp).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn parse_reads_stamped_messages() {
+        assert_eq!(
+            ChatEvent::parse("MSG 3 2024-05-01T10:00:00Z azeez hello there"),
+            ChatEvent::PublicMessage {
+                id: 3,
+                time: time("2024-05-01T10:00:00Z"),
+                reply_to: None,
+                sender: "azeez".to_string(),
+                message: "hello there".to_string(),
+            }
+        );
+        assert_eq!(
+            ChatEvent::parse("RPL 4 2024-05-01T10:00:00Z 3 bob yes"),
+            ChatEvent::PublicMessage {
+                id: 4,
+                time: time("2024-05-01T10:00:00Z"),
+                reply_to: Some(3),
+                sender: "bob".to_string(),
+                message: "yes".to_string(),
+            }
+        );
+        assert_eq!(
+            ChatEvent::parse("THR 2 5 2024-05-01T10:00:00Z bob deeper"),
+            ChatEvent::ThreadMessage {
+                depth: 2,
+                id: 5,
+                time: time("2024-05-01T10:00:00Z"),
+                sender: "bob".to_string(),
+                message: "deeper".to_string(),
+            }
+        );
+        assert_eq!(
+            ChatEvent::parse("MEN 3 2024-05-01T10:00:00Z azeez hi @bob"),
+            ChatEvent::Mentioned {
+                id: 3,
+                time: time("2024-05-01T10:00:00Z"),
+                sender: "azeez".to_string(),
+                message: "hi @bob".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_reads_edits_reactions_and_presence() {
+        assert_eq!(
+            ChatEvent::parse("MED 3 azeez fixed typo"),
+            ChatEvent::MessageEdited { id: 3, sender: "azeez".to_string(), message: "fixed typo".to_string() }
+        );
+        assert_eq!(ChatEvent::parse("MDL 3"), ChatEvent::MessageDeleted(3));
+        assert_eq!(
+            ChatEvent::parse("RAD 3 bob 👍"),
+            ChatEvent::ReactionAdded { id: 3, user: "bob".to_string(), emoji: "👍".to_string() }
+        );
+        assert_eq!(
+            ChatEvent::parse("RRM 3 bob 👍"),
+            ChatEvent::ReactionRemoved { id: 3, user: "bob".to_string(), emoji: "👍".to_string() }
+        );
+        assert_eq!(ChatEvent::parse("TYP bob start"), ChatEvent::Typing { name: "bob".to_string(), started: true });
+        assert_eq!(ChatEvent::parse("TYP bob stop"), ChatEvent::Typing { name: "bob".to_string(), started: false });
+        assert_eq!(
+            ChatEvent::parse("PRS bob away 90 at lunch"),
+            ChatEvent::Presence {
+                name: "bob".to_string(),
+                presence: Presence::Away(Some("at lunch".to_string())),
+                idle: Duration::from_secs(90),
+            }
+        );
+        assert_eq!(
+            ChatEvent::parse("NCK bob robert"),
+            ChatEvent::NickChanged { old: "bob".to_string(), new: "robert".to_string() }
+        );
+    }
+
+    #[test]
+    fn parse_reads_acknowledgements() {
+        assert_eq!(ChatEvent::parse("ACK 7"), ChatEvent::Acknowledged("7".to_string()));
+        assert_eq!(
+            ChatEvent::parse("DLV 7 bob"),
+            ChatEvent::Delivered { id: "7".to_string(), recipient: "bob".to_string() }
+        );
+        assert_eq!(
+            ChatEvent::parse("NAK 7 Client bob does not exist"),
+            ChatEvent::Rejected { id: "7".to_string(), reason: "Client bob does not exist".to_string() }
+        );
+        assert_eq!(
+            ChatEvent::parse("ULI azeez bob"),
+            ChatEvent::UserList(vec!["azeez".to_string(), "bob".to_string()])
+        );
+        assert_eq!(ChatEvent::parse("CDE"), ChatEvent::Disconnected);
+    }
+
+    #[test]
+    fn parse_keeps_malformed_responses_as_unknown() {
+        let malformed = ["MSG 3 yesterday azeez hi", "MSG x 2024-05-01T10:00:00Z azeez hi", "MDL x", "TYP bob maybe", "XYZ what", ""];
+        for response in malformed {
+            assert_eq!(ChatEvent::parse(response), ChatEvent::Unknown(response.to_string()), "{}", response);
+        }
+    }
+}
@@ -1,18 +1,42 @@
 use std::{
-    io::{Read, Write},
-    net::TcpStream,
+    collections::{HashMap, HashSet},
+    io::BufRead,
+    sync::mpsc::{self, RecvTimeoutError},
     thread,
+    time::Duration,
 };
 extern crate colored;
 use colored::*;
 
-use super::tools::{client_log, LogMessagType};
+use chrono::{DateTime, Local, Utc};
+
+use super::chat_client::{describe_idle, ChatClient, ChatError, ChatEvent, Reactions};
+use super::tools::{client_log, mentions, LogMessagType};
+
+/// How long to wait for typed input before checking for new events
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How long a request may go unacknowledged before it is marked
+const ACK_TIMEOUT: Duration = Duration::from_secs(3);
+/// How often to try to reconnect after the connection was lost
+const RECONNECT_ATTEMPTS: u32 = 5;
+/// How long to wait before every reconnection attempt
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
 
 /// # `Client`
-/// Structure that handles a client and its communication with the server
+/// Line based terminal front-end for `ChatClient`: reads lines from stdin and prints what the server sends.
+/// Requests that are not acknowledged in time are marked, and they are sent again when the client reconnects after losing the connection
 pub struct Client {
-    socket: TcpStream,
-    name: String,
+    chat: ChatClient,
+    /// Requests already marked as unacknowledged
+    marked: HashSet<String>,
+    /// Whether the user asked to leave, the client does not reconnect then
+    leaving: bool,
+    /// Reactions on the messages seen so far, by message id
+    reactions: HashMap<u64, Reactions>,
+    /// Whether to ring the terminal bell when mentioned
+    bell: bool,
+    /// Messages that were highlighted for mentioning this client, the server's `MEN` for them is not printed again
+    highlighted: HashSet<u64>,
 }
 
 impl Client {
@@ -20,159 +44,262 @@ impl Client {
     /// Creates a new client by taking the name of the client as `String`
     /// This returns `Option<Client>` where None is returned if the connection was not successful for some reason
     pub fn new(name: String) -> Option<Client> {
-        if let Ok(mut socket) = TcpStream::connect(super::SERVER_ADDRESS) {
-            let mut response_buffer = [0; 32];
-            // Send join request to server
-            if let Err(_) = socket
-                .write(format!("CON {}", name).as_bytes()) {
-                    client_log("Error initiating communication with server".to_string(), LogMessagType::EncounteredError);
-                    return None;
-                }
+        Self::connect(super::SERVER_ADDRESS, name)
+    }
 
-            // Read resposne
-            if let Err(_) = socket
-                .read(&mut response_buffer) {
-                    client_log("Error reading from server".to_string(), LogMessagType::EncounteredError);
-                    return None;
-                }
+    /// # `connect`
+    /// Like `new` but connects to `address`, `host:port` or `unix:<path>`
+    pub fn connect(address: &str, name: String) -> Option<Client> {
+        Self::start(ChatClient::connect(address, &name))
+    }
 
-            let response = String::from_utf8_lossy(&response_buffer)
-                .trim_end_matches('\u{0}') // Remove trailing 0's
-                .to_string();
-
-            match response.as_str() {
-                super::resposne_type::CONNECTION_ACCEPTED => { // Yay
-                    println!("Connection Accepted! Welcome!");
-                    Some(Client {
-                        socket: socket,
-                        name: name,
-                    })
-                }
-                super::resposne_type::CONNECTION_DENIED => { // Sadge
-                    client_log("Connection Denied".to_string(), LogMessagType::EncounteredError);
-                    None
-                }
-                _ => {
-                    client_log("An unknown error occured".to_string(), LogMessagType::EncounteredError);
-                    None
-                }
+    /// # `connect_operator`
+    /// Like `connect` but joins with the operator password, which operator names need
+    pub fn connect_operator(address: &str, name: String, password: &str) -> Option<Client> {
+        Self::start(ChatClient::connect_operator(address, &name, password))
+    }
+
+    fn start(connected: Result<ChatClient, ChatError>) -> Option<Client> {
+        match connected {
+            Ok(chat) => { // Yay
+                println!("Connection Accepted! Welcome!");
+                Some(Client {
+                    chat,
+                    marked: HashSet::new(),
+                    leaving: false,
+                    reactions: HashMap::new(),
+                    bell: false,
+                    highlighted: HashSet::new(),
+                })
+            }
+            Err(ChatError::Denied) => { // Sadge
+                client_log("Connection Denied".to_string(), LogMessagType::EncounteredError);
+                None
+            }
+            Err(e) => {
+                client_log(e.to_string(), LogMessagType::EncounteredError);
+                None
             }
-        } else {
-            None
         }
     }
 
+    /// # `bell`
+    /// Rings the terminal bell whenever a message mentions this client
+    pub fn bell(mut self, bell: bool) -> Client {
+        self.bell = bell;
+        self
+    }
+
     /// # `init`
-    /// Initializes the client. This will block until the client is shut down
+    /// Initializes the client. This will block until the connection is closed
     pub fn init(&mut self) {
-        let mut socket = match self.socket.try_clone() {
-            Ok(val) => val,
-            Err(_) => {
-                client_log("Error cloning client socket".to_string(), LogMessagType::EncounteredError);
-                return;
-            }
-        };
-
-        // Spawn response listening and handling thread
-        thread::spawn(move || loop {
-            let mut buffer = [0; 1024];
-            if let Err(_) = socket.read(&mut buffer) {
-                client_log("Error reading from socket".to_string(), LogMessagType::EncounteredError);
-                std::process::exit(0);
-            }
-            Self::parse_resposne(&buffer);
+        // Stdin is read on its own thread so events keep being printed while nothing is typed
+        let (sender, lines) = mpsc::channel::<String>();
+        thread::spawn(move || {
+            for line in std::io::stdin().lock().lines() {
+                match line {
+                    Ok(line) => {
+                        if sender.send(line).is_err() {
+                            return;
+                        }
+                    }
+                    Err(_) => {
+                        client_log("Error reading from stdin".to_string(), LogMessagType::EncounteredError);
+                        return;
+                    }
+                }
+            }
         });
 
-        // Reads from stdin and send to server
         loop {
-            let mut buffer = String::new();
-            match std::io::stdin()
-                .read_line(&mut buffer) {
-                    Err(_) => {
-                        client_log("Error reading from stdin".to_string(), LogMessagType::EncounteredError);
-                    },
-                    _ => {}
+            let events: Vec<ChatEvent> = self.chat.poll_events().collect();
+            for event in events {
+                let closed = event == ChatEvent::Closed;
+                if !self.print_event(event) {
+                    if closed && !self.leaving && self.reconnect() {
+                        break;
+                    }
+                    return;
                 }
+            }
+            self.mark_unacknowledged();
 
-            if buffer.trim_end().len() == 0 {
-                continue;
-            }
-            match self.parse_message(&buffer) {
-                Ok(buffer) => {
-                    match self.socket
-                        .write(buffer.as_bytes()) {
-                            Err(_) => {
-                                client_log("Error sending to server".to_string(), LogMessagType::EncounteredError);
-                            },
-                            _ => {}
-                        }
+            match lines.recv_timeout(POLL_INTERVAL) {
+                Ok(line) => {
+                    if line.trim_end().is_empty() {
+                        continue;
+                    }
+                    match self.chat.send_input(&line) {
+                        Ok(None) => self.leaving = true,
+                        Ok(Some(_)) => {}
+                        Err(e) => client_log(e.to_string(), LogMessagType::EncounteredError),
+                    }
                 }
-                Err(e) => client_log(format!("{}", e), LogMessagType::EncounteredError)
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
             }
         }
     }
 
-    /// # `parse_response`
-    /// takes a response buffer `&[u8]` and handles it
-    fn parse_resposne(response: &[u8]) {
-        let response = String::from_utf8_lossy(response).to_string();
+    /// # `mark_unacknowledged`
+    /// Prints the requests that have waited longer than `ACK_TIMEOUT` for the server, each one once
+    fn mark_unacknowledged(&mut self) {
+        for request in self.chat.pending() {
+            if request.sent_at.elapsed() >= ACK_TIMEOUT && self.marked.insert(request.id) {
+                println!("{} {}", "[NOT ACKNOWLEDGED]".bold().red(), request.content.dimmed());
+            }
+        }
+    }
 
-        // Get resposne id (first three characters)
-        match &response[..3] {
-            super::resposne_type::CONNECTION_DENIED => { // Sadge
+    /// # `reconnect`
+    /// Tries to get back onto the server after the connection was lost and sends the unacknowledged requests again.
+    /// Returns whether it worked
+    fn reconnect(&mut self) -> bool {
+        for attempt in 1..=RECONNECT_ATTEMPTS {
+            client_log(format!("Reconnecting ({}/{})", attempt, RECONNECT_ATTEMPTS), LogMessagType::Information);
+            thread::sleep(RECONNECT_DELAY);
+            match self.chat.reconnect() {
+                Ok(resent) => {
+                    println!("Reconnected! Sent {} unacknowledged request(s) again", resent);
+                    return true;
+                }
+                Err(e) => client_log(e.to_string(), LogMessagType::EncounteredError),
+            }
+        }
+        false
+    }
+
+    /// # `print_event`
+    /// Prints an event from the server. Returns `false` once the connection is over
+    fn print_event(&mut self, event: ChatEvent) -> bool {
+        match event {
+            ChatEvent::Disconnected => { // Sadge
                 client_log("You have been disconnected".to_string(), LogMessagType::Information);
-                std::process::exit(0);
-            }
-            super::resposne_type::PUBLIC_MESSAGE => { // Public message from some other client
-                // Get sender's name
-                let sender = response.split_ascii_whitespace().nth(1).unwrap();
-                // Get exactly where the actual message content starts
-                let message_start = sender.len() + response.find(sender).unwrap() + 1;
-                let message = response[message_start..].to_string();
-                print!("{}> {}\r", sender.bold().bright_blue(), message);
-            }
-            super::resposne_type::PRIVATE_MESSAGE => { // Private message from some other client
-                // Get sender's name
-                let sender = response.split_ascii_whitespace().nth(1).unwrap();
-                // Get exactly where the actual message is
-                let message_start = sender.len() + response.find(sender).unwrap() + 1;
-                let message = response[message_start..].to_string();
-                print!(
-                    "{} {}: {}\r",
+                return false;
+            }
+            ChatEvent::Closed => {
+                client_log("Server closed the connection".to_string(), LogMessagType::Information);
+                return false;
+            }
+            ChatEvent::PublicMessage { id, time, reply_to, sender, message } => { // Public message from some other client
+                // Replies are marked with the message they reply to
+                let marker = reply_to.map(|parent| format!("↳ #{} ", parent)).unwrap_or_default();
+                let name = self.chat.name();
+                if sender != name && mentions(&message, &name) {
+                    // Messages that mention this client are highlighted
+                    self.highlighted.insert(id);
+                    println!(
+                        "{}{} {} {}{} {}> {}",
+                        if self.bell { "\u{7}" } else { "" },
+                        local_time(time).dimmed(),
+                        format!("#{}", id).dimmed(),
+                        marker.dimmed(),
+                        "[MENTION]".bold().yellow(),
+                        sender.bold().yellow(),
+                        message.bold()
+                    );
+                    return true;
+                }
+                println!(
+                    "{} {} {}{}> {}",
+                    local_time(time).dimmed(),
+                    format!("#{}", id).dimmed(),
+                    marker.dimmed(),
+                    sender.bold().bright_blue(),
+                    message
+                );
+            }
+            ChatEvent::ThreadMessage { depth, id, time, sender, message } => { // Replies are indented under the message they reply to
+                if depth == 0 {
+                    println!("{}", format!("Thread of #{}", id).bold());
+                }
+                let indent = if depth == 0 { String::new() } else { format!("{}↳ ", "  ".repeat(depth - 1)) };
+                println!(
+                    "{} {} {}{}> {}",
+                    local_time(time).dimmed(),
+                    format!("#{}", id).dimmed(),
+                    indent,
+                    sender.bold().bright_blue(),
+                    message
+                );
+            }
+            ChatEvent::MessageEdited { id, sender, message } => { // Edits are printed again with a mark, the old line stays where it is
+                println!(
+                    "{} {} {}> {} {}",
+                    local_time(Utc::now()).dimmed(),
+                    format!("#{}", id).dimmed(),
+                    sender.bold().bright_blue(),
+                    message,
+                    "(edited)".italic().dimmed()
+                );
+            }
+            ChatEvent::MessageDeleted(id) => {
+                self.reactions.remove(&id);
+                println!("{} {}", format!("#{}", id).dimmed(), "[message deleted]".italic().dimmed());
+            }
+            ChatEvent::PrivateMessage { time, sender, message, .. } => { // Private message from some other client
+                println!(
+                    "{} {} {}: {}",
+                    local_time(time).dimmed(),
                     sender.italic().bright_blue(),
                     "whispered".italic(),
                     message.italic()
                 );
             },
-            super::resposne_type::USER_JOINED => {
-                let joined = response[4..].to_string();
+            ChatEvent::Mentioned { id, time, sender, message } => { // Mentions made while offline, the others were highlighted already
+                if self.highlighted.remove(&id) {
+                    return true;
+                }
+                println!(
+                    "{}{} {} {} {}> {}",
+                    if self.bell { "\u{7}" } else { "" },
+                    local_time(time).dimmed(),
+                    format!("#{}", id).dimmed(),
+                    "[MENTION]".bold().yellow(),
+                    sender.bold().yellow(),
+                    message.bold()
+                );
+            }
+            ChatEvent::UserJoined(joined) => {
                 println!("{} {}", joined.bold().blue(), "joined the server!".italic());
             },
-            super::resposne_type::USER_LEFT => {
-                let left = response[4..].to_string();
+            ChatEvent::UserLeft(left) => {
                 println!("{} {}", left.bold().blue(), "left the server!".italic());
+            },
+            ChatEvent::NickChanged { old, new } => {
+                println!("{} {} {}", old.bold().blue(), "is now known as".italic(), new.bold().blue());
+            }
+            ChatEvent::System(message) => { // Message from the server itself
+                println!("{} {}", "[SYSTEM]".bold().yellow(), message.yellow());
+            },
+            ChatEvent::UserList(names) => {
+                println!("{} {}", "Online:".bold(), names.join(", "));
             }
-            _ => {}
+            ChatEvent::Presence { name, presence, idle } => {
+                println!("{} {} {}", name.bold().blue(), format!("is {}", presence).italic(), format!("(idle {})", describe_idle(idle)).dimmed());
+            }
+            ChatEvent::Error(reason) | ChatEvent::Rejected { reason, .. } => {
+                client_log(reason, LogMessagType::EncounteredError);
+            }
+            ChatEvent::Delivered { recipient, .. } => {
+                println!("{}", format!("(delivered to {})", recipient).dimmed());
+            }
+            ChatEvent::ReactionAdded { id, .. } | ChatEvent::ReactionRemoved { id, .. } => { // The whole tally is printed again under the message id
+                let reactions = self.reactions.entry(id).or_default();
+                reactions.update(&event);
+                let summary = if reactions.is_empty() { "no reactions".italic().to_string() } else { reactions.summary() };
+                println!("{} {}", format!("#{}", id).dimmed(), summary);
+            }
+            ChatEvent::Acknowledged(_) => {}
+            ChatEvent::Typing { .. } => {} // Nowhere to show it without getting in the way of what is typed
+            ChatEvent::Unknown(_) => {}
         }
+        true
     }
+}
 
-    /// # `parse_message`
-    /// Takes a message as `&String` and returns it in request format (with identifier and other neccessary content for the server to interpert).
-    /// This will however return it as `Result<String, &str>` where Error is returned if a command was given which is not known to this application.
-    fn parse_message(&self, message: &String) -> Result<String, &str> {
-        // Gets what ID to return (first 3 characters of a request)
-        let identifier = if message.starts_with('/') { // Commands starts with a / such as /whisper
-            let command = message.split_ascii_whitespace().nth(0).unwrap();
-            if super::commands::LIST.contains(&command) { 
-                "CMD"
-            } else { // If the given command does not exist tell client
-                return Err("No such command");
-            }
-        } else {
-            "MSG"
-        };
-
-        // This is how the request will look like
-        Ok(format!("{} {} {}", identifier, self.name, message))
-    }
+/// # `local_time`
+/// A time stamped by the server, as the local time of day
+fn local_time(time: DateTime<Utc>) -> String {
+    time.with_timezone(&Local).format("%H:%M:%S").to_string()
 }
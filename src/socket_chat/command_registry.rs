@@ -0,0 +1,675 @@
+use std::sync::{Arc, Mutex};
+
+use super::hooks::HookList;
+use super::tools::{audit, AuditEvent, BanList, Config, History, Mentions, Presence, Stamp, HISTORY_SIZE};
+use super::{commands, resposne_type, ClientList};
+
+/// Longest reaction in characters, enough for emoji made of several code points and `:shortcodes:`
+const MAX_EMOJI_LENGTH: usize = 16;
+
+/// # `CommandHandler`
+/// Runs a command. Gets the context and everything after the command name, trimmed.
+/// `Err(reason)` is sent back to the sender as an error response
+pub type CommandHandler = Box<dyn Fn(&mut CommandContext, &str) -> Result<(), String> + Send + Sync>;
+
+/// # `CommandContext`
+/// What a command handler gets to work with
+pub struct CommandContext<'a> {
+    /// Who sent the command
+    pub sender: &'a str,
+    /// Everyone connected, already locked
+    pub clients: &'a mut ClientList,
+    /// The server's current config
+    pub config: &'a Arc<Mutex<Config>>,
+    /// Names and addresses that are not allowed on the server
+    pub(crate) bans: &'a Mutex<BanList>,
+    /// Every command the server knows
+    pub registry: &'a CommandRegistry,
+    pub(crate) hooks: &'a HookList,
+    /// The latest public messages
+    pub(crate) history: &'a Mutex<History>,
+    pub(crate) mentions: &'a Mutex<Mentions>,
+    /// Who the command delivered something to, the sender gets a receipt for each
+    pub(crate) receipts: Vec<String>,
+    /// The new name of the sender if the command renamed them, the acknowledgement goes there
+    pub(crate) renamed: Option<String>,
+}
+
+impl CommandContext<'_> {
+    /// # `reply`
+    /// Sends a system message to the sender of the command
+    pub fn reply(&mut self, message: &str) {
+        let _ = self
+            .clients
+            .send_to(&self.sender.to_string(), &format!("{} {}", resposne_type::SYSTEM, message));
+    }
+
+    /// # `is_operator`
+    /// Whether the sender joined with the operator password and still goes by one of the operator names in the config
+    pub fn is_operator(&self) -> bool {
+        self.clients.joined_as_operator(&self.sender.to_string())
+            && self
+                .config
+                .lock()
+                .map(|config| config.operators.iter().any(|operator| operator == self.sender))
+                .unwrap_or(false)
+    }
+
+    /// # `delivered`
+    /// Records that a message of the sender was written to `recipient`. If the request carried an id the sender gets a delivery receipt
+    pub fn delivered(&mut self, recipient: &str) {
+        self.receipts.push(recipient.to_string());
+    }
+}
+
+/// # `Command`
+/// A command clients can run: its name, other names it answers to, its arguments, a line of help, longer usage notes and what it does
+pub struct Command {
+    name: String,
+    aliases: Vec<String>,
+    args: Vec<String>,
+    help: String,
+    details: Vec<String>,
+    handler: CommandHandler,
+}
+
+impl Command {
+    /// # `new`
+    /// Creates a command without aliases or arguments. `name` includes the `/` (`/w`)
+    pub fn new<F>(name: &str, help: &str, handler: F) -> Command
+    where
+        F: Fn(&mut CommandContext, &str) -> Result<(), String> + Send + Sync + 'static,
+    {
+        Command {
+            name: name.to_string(),
+            aliases: Vec::new(),
+            args: Vec::new(),
+            help: help.to_string(),
+            details: Vec::new(),
+            handler: Box::new(handler),
+        }
+    }
+
+    /// # `alias`
+    /// Adds another name the command answers to
+    pub fn alias(mut self, alias: &str) -> Command {
+        self.aliases.push(alias.to_string());
+        self
+    }
+
+    /// # `args`
+    /// Sets the arguments the command takes, in order. Names in `[brackets]` are optional, the others are required
+    /// and the command is not run without them
+    pub fn args(mut self, args: &[&str]) -> Command {
+        self.args = args.iter().map(|arg| arg.to_string()).collect();
+        self
+    }
+
+    /// # `details`
+    /// Sets the usage notes `/help <command>` shows below the usage line, one response line per line of `details`
+    pub fn details(mut self, details: &str) -> Command {
+        self.details = details.lines().map(|line| line.to_string()).collect();
+        self
+    }
+
+    /// # `name`
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// # `help`
+    /// The one line description of the command
+    pub fn help(&self) -> &str {
+        &self.help
+    }
+
+    /// # `usage`
+    /// How to call the command (`/w <target> <message>`)
+    pub fn usage(&self) -> String {
+        let mut usage = self.name.clone();
+        for arg in self.args.iter() {
+            if arg.starts_with('[') {
+                usage += &format!(" {}", arg);
+            } else {
+                usage += &format!(" <{}>", arg);
+            }
+        }
+        usage
+    }
+
+    /// # `describe`
+    /// Everything `/help <command>` shows: usage, description, aliases and usage notes
+    pub fn describe(&self) -> Vec<String> {
+        let mut lines = vec![format!("Usage: {}", self.usage()), self.help.clone()];
+        if !self.aliases.is_empty() {
+            lines.push(format!("Also: {}", self.aliases.join(", ")));
+        }
+        lines.extend(self.details.iter().cloned());
+        lines
+    }
+
+    /// # `required_args`
+    /// How many arguments have to be given
+    fn required_args(&self) -> usize {
+        self.args.iter().filter(|arg| !arg.starts_with('[')).count()
+    }
+
+    /// # `answers_to`
+    /// Whether `name` is the command's name or one of its aliases
+    fn answers_to(&self, name: &str) -> bool {
+        self.name == name || self.aliases.iter().any(|alias| alias == name)
+    }
+}
+
+/// # `CommandRegistry`
+/// Every command the server knows. Clients send any `/command` line and the server looks it up here
+pub struct CommandRegistry {
+    commands: Vec<Command>,
+}
+
+impl Default for CommandRegistry {
+    /// The built-in commands: `/w`, `/exit`, `/announce`, `/who`, `/away`, `/busy`, `/back`, `/nick`, `/edit`, `/delete`, `/react`,
+    /// `/reply`, `/thread` and `/help`
+    fn default() -> Self {
+        let mut registry = CommandRegistry::empty();
+        registry.register(
+            Command::new(commands::WHISPER, "Send a private message", whisper)
+                .alias("/whisper")
+                .args(&["target", "message"])
+                .details("Only <target> sees the message. Everything after the name is the message\nExample: /w bob are you there?"),
+        );
+        registry.register(
+            Command::new(commands::LOGOUT, "Leave the server", logout)
+                .details("Everyone else is told you left. Closing the client does the same"),
+        );
+        registry.register(
+            Command::new(commands::ANNOUNCE, "Send a system message to everyone (operators only)", announce)
+                .args(&["message"])
+                .details("Operators are listed in the server's config file\nExample: /announce Server restarts in 5 minutes"),
+        );
+        registry.register(
+            Command::new(commands::WHO, "List who is online", who)
+                .details("Shows the names of everyone that is connected, you included, whether they are away or busy and how long they have been idle"),
+        );
+        registry.register(
+            Command::new(commands::AWAY, "Tell everyone you are away", away)
+                .args(&["[message]"])
+                .details("Whoever whispers you gets the message as an answer until you are /back\nExample: /away lunch, back at 2"),
+        );
+        registry.register(
+            Command::new(commands::BUSY, "Tell everyone you are busy", busy)
+                .args(&["[message]"])
+                .details("Like /away, for when you are there but do not want to be disturbed\nExample: /busy in a meeting"),
+        );
+        registry.register(
+            Command::new(commands::BACK, "Tell everyone you are back", back)
+                .details("Ends /away and /busy"),
+        );
+        registry.register(
+            Command::new(commands::NICK, "Change your name", nick)
+                .args(&["name"])
+                .details("The name has to be a single word nobody else has, and can not be banned, an operator's or one the HTTP API posts as. Everyone is told about the change\nYour messages stay yours to edit and delete\nExample: /nick azeez"),
+        );
+        registry.register(
+            Command::new(commands::EDIT, "Change the text of one of your messages", edit)
+                .args(&["id", "text"])
+                .details(&format!(
+                    "<id> is the number shown in front of the message. Operators may edit anyone's messages\nOnly the latest {} public messages can be edited\nExample: /edit 42 I meant tomorrow",
+                    HISTORY_SIZE
+                )),
+        );
+        registry.register(
+            Command::new(commands::DELETE, "Delete one of your messages", delete)
+                .args(&["id"])
+                .details("Everyone sees the message was deleted. Operators may delete anyone's messages\nExample: /delete 42"),
+        );
+        registry.register(
+            Command::new(commands::REACT, "React to a message with an emoji", react)
+                .args(&["id", "emoji"])
+                .details("Reacting again with the same emoji takes the reaction back\nExample: /react 42 👍"),
+        );
+        registry.register(
+            Command::new(commands::REPLY, "Reply to a message", reply)
+                .args(&["id", "text"])
+                .details("Everyone sees the reply, marked with the message it replies to. Replies can be replied to as well\nExample: /reply 42 Sounds good"),
+        );
+        registry.register(
+            Command::new(commands::THREAD, "Show a message with all its replies", thread)
+                .args(&["id"])
+                .details("<id> can be any message of the thread, the whole thread is shown\nExample: /thread 42"),
+        );
+        registry.register(
+            Command::new(commands::HELP, "List the commands or explain one", help)
+                .args(&["[command]"])
+                .details("Without a command every command is listed with a line about it\nExample: /help w"),
+        );
+        registry
+    }
+}
+
+impl CommandRegistry {
+    /// # `empty`
+    /// A registry without any commands. `/exit` still works, the server handles it itself
+    pub fn empty() -> CommandRegistry {
+        CommandRegistry { commands: Vec::new() }
+    }
+
+    /// # `register`
+    /// Adds a command. A command with the same name replaces the existing one
+    pub fn register(&mut self, command: Command) {
+        self.commands.retain(|existing| existing.name != command.name);
+        self.commands.push(command);
+    }
+
+    /// # `find`
+    /// The command answering to `name`, if any
+    pub fn find(&self, name: &str) -> Option<&Command> {
+        self.commands.iter().find(|command| command.answers_to(name))
+    }
+
+    /// # `commands`
+    /// Every registered command in the order they were added
+    pub fn commands(&self) -> &[Command] {
+        &self.commands
+    }
+
+    /// # `run`
+    /// Looks up the command of `line` (`/w bob hi`) and runs it for `context.sender`.
+    /// Returns `Err` with the reason if the command is unknown, is missing arguments or failed
+    pub fn run(context: &mut CommandContext, line: &str) -> Result<(), String> {
+        let line = line.trim();
+        let (name, args) = line.split_once(' ').map(|(name, args)| (name, args.trim())).unwrap_or((line, ""));
+        let registry = context.registry;
+        let command = registry
+            .find(name)
+            .ok_or_else(|| format!("Unknown command {}, try {}", name, commands::HELP))?;
+
+        // Malformed invocations are answered with how to call the command
+        if args.split_ascii_whitespace().count() < command.required_args() {
+            return Err(format!("Usage: {}", command.usage()));
+        }
+        (command.handler)(context, args)
+    }
+}
+
+/// # `whisper`
+/// Sends a private message to the target
+fn whisper(context: &mut CommandContext, args: &str) -> Result<(), String> {
+    let (target, message) = args.split_once(char::is_whitespace).ok_or("Missing message")?;
+    if context.clients.name_exists(&target.to_string()).is_none() {
+        return Err(format!("{} is not online", target));
+    }
+    context
+        .clients
+        .send_to(
+            &target.to_string(),
+            &format!("{} {} {} {}", resposne_type::PRIVATE_MESSAGE, Stamp::next(), context.sender, message.trim()),
+        )
+        .map_err(|_| format!("Could not deliver to {}", target))?;
+    context.delivered(target);
+
+    // Away and busy clients answer with their status
+    match context.clients.presence_of(&target.to_string()).cloned() {
+        Some(Presence::Online) | None => {}
+        Some(presence) => context.reply(&format!("{} is {}", target, presence)),
+    }
+    Ok(())
+}
+
+/// # `logout`
+/// `/exit` as `/help` shows it. The server logs clients out before the registry is asked (see `disconnect`), this only
+/// runs if it is called under another name
+fn logout(context: &mut CommandContext, _args: &str) -> Result<(), String> {
+    disconnect(context.clients, context.hooks, context.sender)
+}
+
+/// # `disconnect`
+/// Removes `sender` from the server and tells everyone else. `/exit` and lost connections end up here without going
+/// through the registry, so a custom registry can not keep clients from leaving
+pub(crate) fn disconnect(clients: &mut ClientList, hooks: &HookList, sender: &str) -> Result<(), String> {
+    let sender = sender.to_string();
+    let address = clients.address_of(&sender);
+    clients.remove(sender.clone()).map_err(|e| e.to_string())?;
+    audit(AuditEvent::Leave, Some(&sender), address.map(|address| address.to_string()), None);
+    for hook in hooks.iter() {
+        hook.on_disconnect(&sender);
+    }
+
+    // Tell other clients
+    clients.send_to_all(&format!("{} {}", resposne_type::USER_LEFT, sender));
+    Ok(())
+}
+
+/// # `announce`
+/// Sends a system message to everyone, operators only
+fn announce(context: &mut CommandContext, args: &str) -> Result<(), String> {
+    if !context.is_operator() {
+        return Err(format!("Only operators can use {}", commands::ANNOUNCE));
+    }
+    context.clients.send_to_all(&format!("{} {}", resposne_type::SYSTEM, args));
+    Ok(())
+}
+
+/// # `message_id`
+/// The id of a message as it was typed, with or without the `#` in front
+fn message_id(id: &str) -> Result<u64, String> {
+    id.trim_start_matches('#')
+        .parse()
+        .map_err(|_| format!("Invalid message id {}", id))
+}
+
+/// # `changeable_message`
+/// Parses a message id (`42` or `#42`) and checks the sender may change that message: it is still in the history
+/// and the sender's connection wrote it, whatever it was called then, or the sender is an operator. Returns the id
+fn changeable_message(context: &CommandContext, id: &str) -> Result<u64, String> {
+    let id = message_id(id)?;
+    let author = context
+        .history
+        .lock()
+        .map_err(|e| e.to_string())?
+        .find(id)
+        .map(|entry| entry.author)
+        .ok_or_else(|| format!("No message {} among the latest public messages", id))?;
+    let own = author.is_some() && author == context.clients.id_of(&context.sender.to_string());
+    if !own && !context.is_operator() {
+        return Err("You can only change your own messages".to_string());
+    }
+    Ok(id)
+}
+
+/// # `edit`
+/// Changes the text of a public message and tells everyone. The new text goes through the message hooks like a new message
+fn edit(context: &mut CommandContext, args: &str) -> Result<(), String> {
+    let (id, text) = args.split_once(char::is_whitespace).ok_or("Missing text")?;
+    let id = changeable_message(context, id)?;
+    let mut text = text.trim().to_string();
+    if let Some(reason) = context.hooks.iter().find_map(|hook| hook.on_message(context.sender, &mut text).err()) {
+        return Err(reason);
+    }
+
+    let mut history = context.history.lock().map_err(|e| e.to_string())?;
+    let sender = history.find(id).map(|entry| entry.sender.clone()).unwrap_or_default();
+    history.edit(id, &text);
+    context.clients.send_to_all(&format!("{} {} {} {}", resposne_type::MESSAGE_EDITED, id, sender, text));
+    Ok(())
+}
+
+/// # `delete`
+/// Deletes a public message and tells everyone
+fn delete(context: &mut CommandContext, args: &str) -> Result<(), String> {
+    let id = changeable_message(context, args.trim())?;
+    if let Ok(mut history) = context.history.lock() {
+        history.delete(id);
+    }
+    context.clients.send_to_all(&format!("{} {}", resposne_type::MESSAGE_DELETED, id));
+    Ok(())
+}
+
+/// # `react`
+/// Adds or takes back a reaction of the sender to a public message and tells everyone
+fn react(context: &mut CommandContext, args: &str) -> Result<(), String> {
+    let (id, emoji) = args.split_once(char::is_whitespace).ok_or("Missing emoji")?;
+    let id = message_id(id)?;
+    let emoji = emoji.trim();
+    if emoji.contains(char::is_whitespace) || emoji.chars().count() > MAX_EMOJI_LENGTH {
+        return Err("A reaction is a single emoji".to_string());
+    }
+
+    let added = context
+        .history
+        .lock()
+        .map_err(|e| e.to_string())?
+        .toggle_reaction(id, context.sender, emoji)?;
+    let response = if added { resposne_type::REACTION_ADDED } else { resposne_type::REACTION_REMOVED };
+    context.clients.send_to_all(&format!("{} {} {} {}", response, id, context.sender, emoji));
+    Ok(())
+}
+
+/// # `who`
+/// Everyone that is connected
+fn who(context: &mut CommandContext, _args: &str) -> Result<(), String> {
+    let sender = context.sender.to_string();
+    let names = context.clients.names();
+    let _ = context
+        .clients
+        .send_to(&sender, &format!("{} {}", resposne_type::USER_LIST, names.join(" ")));
+    for name in names {
+        if let Some(presence) = context.clients.presence_response(&name) {
+            let _ = context.clients.send_to(&sender, &presence);
+        }
+    }
+    Ok(())
+}
+
+/// # `set_presence`
+/// Changes the presence of the sender and tells everyone
+fn set_presence(context: &mut CommandContext, presence: Presence) -> Result<(), String> {
+    let sender = context.sender.to_string();
+    context.clients.set_presence(&sender, presence).map_err(|e| e.to_string())?;
+    if let Some(presence) = context.clients.presence_response(&sender) {
+        context.clients.send_to_all(&presence);
+    }
+    Ok(())
+}
+
+/// # `status_message`
+/// The optional message of `/away` and `/busy`
+fn status_message(args: &str) -> Option<String> {
+    Some(args.trim().to_string()).filter(|message| !message.is_empty())
+}
+
+/// # `away`
+fn away(context: &mut CommandContext, args: &str) -> Result<(), String> {
+    set_presence(context, Presence::Away(status_message(args)))
+}
+
+/// # `busy`
+fn busy(context: &mut CommandContext, args: &str) -> Result<(), String> {
+    set_presence(context, Presence::Busy(status_message(args)))
+}
+
+/// # `back`
+fn back(context: &mut CommandContext, _args: &str) -> Result<(), String> {
+    if context.clients.presence_of(&context.sender.to_string()) == Some(&Presence::Online) {
+        return Err("You are neither away nor busy".to_string());
+    }
+    set_presence(context, Presence::Online)
+}
+
+/// # `nick`
+/// Renames the sender and tells everyone. Their messages so far keep the old name but stay theirs to edit.
+/// Banned names are refused like when joining, and so are the names API tokens post as and those of operators unless the sender is one
+fn nick(context: &mut CommandContext, args: &str) -> Result<(), String> {
+    let old = context.sender.to_string();
+    let new = args.trim();
+    if context.bans.lock().map(|bans| bans.is_banned(new, None)).unwrap_or(true) {
+        return Err(format!("{} is banned", new));
+    }
+    let (operator_name, api_name) = context
+        .config
+        .lock()
+        .map(|config| (config.operators.iter().any(|operator| operator == new), config.is_api_name(new)))
+        .unwrap_or((true, true));
+    if operator_name && !context.is_operator() {
+        return Err(format!("{} is reserved for an operator", new));
+    }
+    if api_name {
+        return Err(format!("{} is reserved for the HTTP API", new));
+    }
+    context.clients.rename(&old, new).map_err(|e| e.to_string())?;
+    let queued = context.mentions.lock().map(|mut mentions| mentions.renamed(&old, new)).unwrap_or_default();
+    let address = context.clients.address_of(&new.to_string());
+    audit(AuditEvent::Rename, Some(&old), address.map(|address| address.to_string()), Some(new.to_string()));
+    context.clients.send_to_all(&format!("{} {} {}", resposne_type::NICK_CHANGED, old, new));
+    for mention in queued {
+        let _ = context.clients.send_to(&new.to_string(), &mention);
+    }
+    context.renamed = Some(new.to_string());
+    Ok(())
+}
+
+/// # `reply`
+/// Sends a public message that replies to another one. The text goes through the message hooks like any message
+fn reply(context: &mut CommandContext, args: &str) -> Result<(), String> {
+    let (id, text) = args.split_once(char::is_whitespace).ok_or("Missing text")?;
+    let parent = message_id(id)?;
+    let mut text = text.trim().to_string();
+    if let Some(reason) = context.hooks.iter().find_map(|hook| hook.on_message(context.sender, &mut text).err()) {
+        return Err(reason);
+    }
+
+    let mut history = context.history.lock().map_err(|e| e.to_string())?;
+    if history.find(parent).is_none() {
+        return Err(format!("No message {} among the latest public messages", parent));
+    }
+    let stamp = Stamp::next();
+    history.push(stamp, Some(parent), context.sender, context.clients.id_of(&context.sender.to_string()), &text);
+    context
+        .clients
+        .send_to_all(&format!("{} {} {} {} {}", resposne_type::REPLY, stamp, parent, context.sender, text));
+    if let Ok(mut mentions) = context.mentions.lock() {
+        mentions.notify(context.clients, stamp, context.sender, &text);
+    }
+    Ok(())
+}
+
+/// # `thread`
+/// Sends the sender every remembered message of the thread a message belongs to
+fn thread(context: &mut CommandContext, args: &str) -> Result<(), String> {
+    let id = message_id(args.trim())?;
+    let thread = context
+        .history
+        .lock()
+        .map_err(|e| e.to_string())?
+        .thread(id)
+        .ok_or_else(|| format!("No message {} among the latest public messages", id))?;
+
+    let sender = context.sender.to_string();
+    for (depth, entry) in thread {
+        let stamp = Stamp { id: entry.id, time: entry.time };
+        context.clients.send_to(
+            &sender,
+            &format!("{} {} {} {} {}", resposne_type::THREAD_MESSAGE, depth, stamp, entry.sender, entry.message),
+        )?;
+    }
+    Ok(())
+}
+
+/// # `help`
+/// One line per command (how to call it and what it does), or everything about one command if its name is given
+fn help(context: &mut CommandContext, args: &str) -> Result<(), String> {
+    let lines: Vec<String> = match args.split_ascii_whitespace().next() {
+        Some(name) => {
+            // "/help w" works as well as "/help /w"
+            let name = if name.starts_with('/') { name.to_string() } else { format!("/{}", name) };
+            context
+                .registry
+                .find(&name)
+                .ok_or_else(|| format!("Unknown command {}, try {}", name, commands::HELP))?
+                .describe()
+        }
+        None => context
+            .registry
+            .commands()
+            .iter()
+            .map(|command| format!("{} - {}", command.usage(), command.help()))
+            .collect(),
+    };
+    for line in lines {
+        context.reply(&line);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `line` for `azeez` with nobody connected
+    fn run(registry: &CommandRegistry, line: &str) -> Result<(), String> {
+        let mut clients = ClientList::new();
+        let config = Arc::new(Mutex::new(Config::default()));
+        let bans = Mutex::new(BanList::new());
+        let hooks: HookList = Arc::new(Vec::new());
+        let history = Mutex::new(History::default());
+        let mentions = Mutex::new(Mentions::new());
+        let mut context = CommandContext {
+            sender: "azeez",
+            clients: &mut clients,
+            config: &config,
+            bans: &bans,
+            registry,
+            hooks: &hooks,
+            history: &history,
+            mentions: &mentions,
+            receipts: Vec::new(),
+            renamed: None,
+        };
+        CommandRegistry::run(&mut context, line)
+    }
+
+    /// A registry with `/echo <first> [rest]` (also `/e`), which remembers the arguments it got
+    fn echo() -> (CommandRegistry, Arc<Mutex<Vec<String>>>) {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let recorded = calls.clone();
+        let mut registry = CommandRegistry::empty();
+        registry.register(
+            Command::new("/echo", "Repeat", move |_: &mut CommandContext, args: &str| {
+                recorded.lock().unwrap().push(args.to_string());
+                Ok(())
+            })
+            .alias("/e")
+            .args(&["first", "[rest]"]),
+        );
+        (registry, calls)
+    }
+
+    #[test]
+    fn run_dispatches_by_name_and_alias_with_trimmed_arguments() {
+        let (registry, calls) = echo();
+        assert_eq!(run(&registry, "/echo one two"), Ok(()));
+        assert_eq!(run(&registry, "  /e   one  "), Ok(()));
+        assert_eq!(*calls.lock().unwrap(), vec!["one two", "one"]);
+    }
+
+    #[test]
+    fn run_answers_missing_arguments_with_usage() {
+        let (registry, calls) = echo();
+        assert_eq!(run(&registry, "/echo"), Err("Usage: /echo <first> [rest]".to_string()));
+        assert_eq!(run(&registry, "/e   "), Err("Usage: /echo <first> [rest]".to_string()));
+        assert!(calls.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn run_refuses_unknown_commands() {
+        let (registry, _) = echo();
+        assert_eq!(
+            run(&registry, "/nope x"),
+            Err(format!("Unknown command /nope, try {}", commands::HELP))
+        );
+    }
+
+    #[test]
+    fn register_replaces_a_command_with_the_same_name() {
+        let (mut registry, calls) = echo();
+        registry.register(Command::new("/echo", "Fail", |_: &mut CommandContext, _: &str| Err("replaced".to_string())));
+        assert_eq!(registry.commands().len(), 1);
+        assert_eq!(run(&registry, "/echo"), Err("replaced".to_string()));
+        assert!(calls.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn default_commands_check_their_arguments() {
+        let registry = CommandRegistry::default();
+        assert_eq!(registry.find("/whisper").map(Command::name), Some(commands::WHISPER));
+        assert_eq!(run(&registry, "/w bob"), Err("Usage: /w <target> <message>".to_string()));
+        assert_eq!(run(&registry, "/w bob hi"), Err("bob is not online".to_string()));
+        assert_eq!(run(&registry, "/delete x"), Err("Invalid message id x".to_string()));
+        assert_eq!(
+            run(&registry, "/edit #3 hi"),
+            Err("No message 3 among the latest public messages".to_string())
+        );
+        assert_eq!(run(&registry, "/help"), Ok(()));
+        assert!(run(&registry, "/help nope").is_err());
+    }
+}
@@ -0,0 +1,192 @@
+use std::{
+    net::SocketAddr,
+    sync::{mpsc::Sender, Arc, Mutex},
+};
+
+use super::hooks::HookList;
+use super::server::{split_request_id, Limits, Server};
+use super::tools::{audit, metered_lock, AuditEvent, BanList, ClientName, Config, Connection, Mentions, METRICS};
+use super::transport::describe_peer;
+use super::{request_type, resposne_type, server_log, ClientList, LogMessagType};
+
+/// # `Gate`
+/// Decides who may join. Shared by every listener so TCP and WebSocket clients are let in the same way
+#[derive(Clone)]
+pub(crate) struct Gate {
+    pub clients: Arc<Mutex<ClientList>>,
+    pub bans: Arc<Mutex<BanList>>,
+    pub config: Arc<Mutex<Config>>,
+    pub hooks: HookList,
+    pub limits: Limits,
+    /// Mentions waiting for clients that were offline
+    pub mentions: Arc<Mutex<Mentions>>,
+    /// Where the client's requests go once it joined
+    pub requests: Sender<String>,
+}
+
+impl Gate {
+    /// # `admit`
+    /// Takes the first request of a connection (`CON <name> [password]`) and runs it through the bans, the operator password, the hooks,
+    /// the client limit and the name check.
+    /// If all pass the client is added to the list, greeted and announced, and its name is returned. It follows `/nick`, so the thread
+    /// reading the client's requests always knows who it is. Otherwise the client is denied and `None` is returned.
+    /// `address` is `None` for clients on a Unix socket
+    pub fn admit(&self, request: &str, address: Option<SocketAddr>, mut connection: Connection) -> Option<ClientName> {
+        let peer = describe_peer(address);
+        // Confirm it is the CON request
+        let (name, password) = match request.strip_prefix(request_type::CONNECT).and_then(|name| name.strip_prefix(' ')) {
+            Some(name) if !name.trim().is_empty() => match name.trim().split_once(' ') {
+                Some((name, password)) => (name, Some(password.trim())),
+                None => (name.trim(), None),
+            },
+            _ => {
+                server_log(format!("Client {} sent invalid request", peer), LogMessagType::Warning);
+                METRICS.count_denied_connection();
+                audit(AuditEvent::Denied, None, address.map(|address| address.to_string()), Some("Invalid connection request".to_string()));
+                return None;
+            }
+        };
+        METRICS.count_message(request_type::CONNECT);
+
+        // Banned names and addresses are turned away before they reach the client list
+        let banned = self.bans
+            .lock()
+            .map(|bans| bans.is_banned(name, address.map(|address| address.ip())))
+            .unwrap_or(false);
+        if banned {
+            server_log(format!("Denied access for banned client {} [{}]", name, peer), LogMessagType::Information);
+            METRICS.count_denied_connection();
+            audit(AuditEvent::Denied, Some(name), address.map(|address| address.to_string()), Some("Banned".to_string()));
+            let _ = connection.send_line(resposne_type::CONNECTION_DENIED);
+            return None;
+        }
+
+        // Names API tokens post as are not for clients, the API's messages would be taken for theirs
+        let reserved = self.config.lock().map(|config| config.is_api_name(name)).unwrap_or(true);
+        if reserved {
+            server_log(format!("Denied access for {} [{}] \"Name is reserved for the HTTP API\"", name, peer), LogMessagType::Information);
+            METRICS.count_denied_connection();
+            audit(AuditEvent::Denied, Some(name), address.map(|address| address.to_string()), Some("Reserved for the HTTP API".to_string()));
+            let _ = connection.send_line(resposne_type::CONNECTION_DENIED);
+            return None;
+        }
+
+        // Operator names need the operator password
+        let authenticated = self.config
+            .lock()
+            .map_err(|e| e.to_string())
+            .and_then(|config| config.authenticate(name, password).map_err(|e| e.to_string()));
+        let operator = match authenticated {
+            Ok(operator) => operator,
+            Err(reason) => {
+                server_log(format!("Denied access for {} [{}] \"{}\"", name, peer, reason), LogMessagType::Information);
+                METRICS.count_denied_connection();
+                audit(AuditEvent::AuthFailure, Some(name), address.map(|address| address.to_string()), Some(reason));
+                let _ = connection.send_line(resposne_type::CONNECTION_DENIED);
+                return None;
+            }
+        };
+
+        // Acquire client list
+        let mut clients = match metered_lock(&self.clients) {
+            Ok(val) => val,
+            Err(e) => {
+                server_log(format!("Error \"{}\" acquiring client list from server", e), LogMessagType::EncounteredError);
+                return None;
+            }
+        };
+
+        // Hooks and the client limit get a say before the name is checked
+        let refused = self.hooks
+            .iter()
+            .find_map(|hook| hook.on_connect(name, address).err())
+            .or_else(|| {
+                self.limits
+                    .max_clients
+                    .filter(|max_clients| clients.names().len() >= *max_clients)
+                    .map(|_| "Server is full".to_string())
+            });
+        if let Some(reason) = refused {
+            server_log(format!("Denied access for {} [{}] \"{}\"", name, peer, reason), LogMessagType::Information);
+            METRICS.count_denied_connection();
+            audit(AuditEvent::Denied, Some(name), address.map(|address| address.to_string()), Some(reason));
+            let _ = connection.send_line(resposne_type::CONNECTION_DENIED);
+            return None;
+        }
+
+        // Name already exists or is not a valid name
+        if let Err(reason) = clients.check_name(name) {
+            server_log(format!("Denied access for {} while adding them to client list \"{}\"", peer, reason), LogMessagType::Information);
+            METRICS.count_denied_connection();
+            if clients.name_exists(&name.to_string()).is_some() {
+                audit(AuditEvent::NameCollision, Some(name), address.map(|address| address.to_string()), None);
+            } else {
+                audit(AuditEvent::Denied, Some(name), address.map(|address| address.to_string()), Some(reason.to_string()));
+            }
+            let _ = connection.send_line(resposne_type::CONNECTION_DENIED); // ACCESS DENIED!!!!!!!!!!!!!!!!!!!!!!!!!!
+            return None;
+        }
+
+        // Connection OK!
+        // Tell the client
+        if connection.send_line(resposne_type::CONNECTION_ACCEPTED).is_err() {
+            server_log(format!("Error writing connection acceptance message to {}", peer), LogMessagType::Information);
+        }
+        // Greet them with the message of the day
+        if let Some(motd) = self.config.lock().ok().and_then(|config| config.motd.clone()) {
+            for line in motd.lines() {
+                let _ = connection.send_line(&format!("{} {}", resposne_type::SYSTEM, line));
+            }
+        }
+        let shared_name = match clients.add_connection(name.to_string(), connection, address) {
+            Ok(shared_name) => shared_name,
+            Err(e) => {
+                server_log(format!("Error \"{}\" adding {} to client list", e, peer), LogMessagType::EncounteredError);
+                return None;
+            }
+        };
+        if operator {
+            clients.set_operator(&name.to_string());
+        }
+
+        // Hand over the mentions they missed
+        if let Ok(mut mentions) = self.mentions.lock() {
+            for mention in mentions.joined(name) {
+                let _ = clients.send_to(&name.to_string(), &mention);
+            }
+        }
+
+        // Tell other clients
+        clients.send_to_all(&format!("{} {}", resposne_type::USER_JOINED, name));
+        server_log(format!("Client {} [{}] joined the server", name, peer), LogMessagType::Information);
+        audit(AuditEvent::Join, Some(name), address.map(|address| address.to_string()), None);
+        Some(shared_name)
+    }
+
+    /// # `reject_too_long`
+    /// Tells the client `name` that its request starting with `start` was longer than the server reads, the way the request
+    /// handling rejects it
+    pub fn reject_too_long(&self, name: &ClientName, start: &str) {
+        let name = name.get();
+        let (id, _) = split_request_id(start);
+        if let Ok(mut clients) = metered_lock(&self.clients) {
+            Server::reject(&mut clients, &name, id, &format!("Message is too long (at most {} bytes)", self.limits.line_length()));
+        }
+    }
+
+    /// # `forward`
+    /// Hands a request line of the client `name` to the request handling thread. The sender of every request has to be the client
+    /// itself, a request in anyone else's name is rejected here. Returns `false` if the request handling thread is gone
+    pub fn forward(&self, name: &ClientName, line: &str) -> bool {
+        let name = name.get();
+        let (id, request) = split_request_id(line);
+        if request.split_ascii_whitespace().nth(1) != Some(name.as_str()) {
+            server_log(format!("Client {} sent a request in another name \"{}\"", name, line), LogMessagType::Warning);
+            if let Ok(mut clients) = metered_lock(&self.clients) {
+                Server::reject(&mut clients, &name, id, "Requests can only be sent in your own name");
+            }
+            return true;
+        }
+        self.requests.send(line.to_string()).is_ok()
+    }
+}
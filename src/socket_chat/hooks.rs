@@ -0,0 +1,33 @@
+use std::net::SocketAddr;
+
+/// # `ServerHooks`
+/// Lets code embedding the server look at traffic as it passes through. Every method has a default that lets everything through,
+/// so implementors only override what they need. Returning `Err(reason)` rejects the traffic, `reason` is sent back to the client as an error response
+/// (or written to the audit log for connections, which are simply denied).
+/// Hooks run on the server's request handling thread while it holds the client list, so they should return quickly
+pub trait ServerHooks: Send + Sync {
+    /// # `on_connect`
+    /// Called when `name` asks to join from `address` (`None` on a Unix socket), before the name is checked. `Err` denies the connection
+    fn on_connect(&self, _name: &str, _address: Option<SocketAddr>) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// # `on_message`
+    /// Called for every public message. `message` can be changed in place, the changed text is what gets broadcast
+    fn on_message(&self, _sender: &str, _message: &mut String) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// # `on_command`
+    /// Called for every command line (such as `/w bob hi`) except `/exit`, which can not be rejected. `command` can be changed in place
+    fn on_command(&self, _sender: &str, _command: &mut String) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// # `on_disconnect`
+    /// Called after `name` left or was disconnected
+    fn on_disconnect(&self, _name: &str) {}
+}
+
+/// The hooks a server was built with, shared between its threads
+pub(crate) type HookList = std::sync::Arc<Vec<Box<dyn ServerHooks>>>;
@@ -0,0 +1,223 @@
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::gate::Gate;
+use super::server::ServerHandle;
+use super::tools::{audit, metered_lock, AuditEvent, History};
+use super::{commands, request_type, server_log, LogMessagType};
+
+/// Largest request body the API accepts
+const MAX_BODY_SIZE: usize = 64 * 1024;
+/// Largest request line and headers together, the request fails beyond that
+const MAX_HEAD_SIZE: u64 = 16 * 1024;
+
+/// # `Request`
+/// The parts of an HTTP request the API looks at
+struct Request {
+    method: String,
+    path: String,
+    query: Option<String>,
+    token: Option<String>,
+    body: Vec<u8>,
+}
+
+/// # `PostBody`
+/// JSON body of `POST /messages` and `POST /whisper/{user}`
+#[derive(Deserialize)]
+struct PostBody {
+    message: String,
+}
+
+/// # `serve_http`
+/// Answers the HTTP API on `listener` from a background thread. Every connection carries one request
+pub(crate) fn serve_http(listener: TcpListener, gate: Gate, history: Arc<Mutex<History>>, handle: ServerHandle) {
+    if let Ok(address) = listener.local_addr() {
+        server_log(format!("HTTP API listening on http://{}", address), LogMessagType::Information);
+    }
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            if !handle.is_running() {
+                break;
+            }
+            if let Ok(stream) = stream {
+                let gate = gate.clone();
+                let history = history.clone();
+                thread::spawn(move || {
+                    if let Err(e) = handle_connection(stream, &gate, &history) {
+                        server_log(format!("Error \"{}\" answering HTTP request", e), LogMessagType::EncounteredError);
+                    }
+                });
+            }
+        }
+    });
+}
+
+/// # `handle_connection`
+/// Reads one request, answers it and closes the connection
+fn handle_connection(mut stream: TcpStream, gate: &Gate, history: &Mutex<History>) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let address = stream.peer_addr()?;
+    let (status, body) = match read_request(&mut BufReader::new(&stream)) {
+        Ok(request) => route(&request, gate, history, address),
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => ("400 Bad Request", json!({ "error": e.to_string() })),
+        Err(e) => return Err(e),
+    };
+
+    let body = body.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+/// # `read_request`
+/// Reads the request line, the headers and a body of `Content-Length` bytes
+fn read_request(reader: &mut impl BufRead) -> io::Result<Request> {
+    // Lines are only read from here on, so one that never ends stops at the limit instead of filling the memory
+    let mut head = io::Read::take(&mut *reader, MAX_HEAD_SIZE);
+    let mut request_line = String::new();
+    head.read_line(&mut request_line)?;
+    let mut parts = request_line.split_ascii_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid request line")),
+    };
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path.to_string(), Some(query.to_string())),
+        None => (target.to_string(), None),
+    };
+
+    let mut token = None;
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        if head.read_line(&mut line)? == 0 {
+            if head.limit() == 0 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "Headers too large"));
+            }
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed during headers"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((header, value)) = line.split_once(':') {
+            match header.trim().to_ascii_lowercase().as_str() {
+                "authorization" => token = value.trim().strip_prefix("Bearer ").map(|token| token.trim().to_string()),
+                "content-length" => {
+                    length = value
+                        .trim()
+                        .parse()
+                        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid Content-Length"))?
+                }
+                _ => {}
+            }
+        }
+    }
+    if length > MAX_BODY_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Body too large"));
+    }
+
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body)?;
+    Ok(Request { method, path, query, token, body })
+}
+
+/// # `route`
+/// Checks the token and answers the request. Returns the status line and the JSON body
+fn route(request: &Request, gate: &Gate, history: &Mutex<History>, address: SocketAddr) -> (&'static str, Value) {
+    let user = gate
+        .config
+        .lock()
+        .ok()
+        .and_then(|config| config.api_user(request.token.as_deref()?).map(|user| user.to_string()));
+    let user = match user {
+        Some(user) => user,
+        None => {
+            let reason = if request.token.is_some() { "Invalid API token" } else { "Missing API token" };
+            server_log(format!("HTTP request from {} refused \"{}\"", address, reason), LogMessagType::Warning);
+            audit(AuditEvent::AuthFailure, None, Some(address.to_string()), Some(reason.to_string()));
+            return ("401 Unauthorized", json!({ "error": reason }));
+        }
+    };
+
+    let whisper_target = request.path.strip_prefix("/whisper/").filter(|target| !target.is_empty());
+    match (request.method.as_str(), request.path.as_str(), whisper_target) {
+        ("POST", "/messages", _) => match message(request) {
+            Ok(message) => forward(gate, format!("{} {} {}", request_type::MESSAGE, user, message)),
+            Err(error) => error,
+        },
+        ("POST", _, Some(target)) => {
+            let online = metered_lock(&gate.clients)
+                .map(|clients| clients.name_exists(&target.to_string()).is_some())
+                .unwrap_or(false);
+            if !online {
+                return ("404 Not Found", json!({ "error": format!("{} is not online", target) }));
+            }
+            match message(request) {
+                Ok(message) => forward(
+                    gate,
+                    format!("{} {} {} {} {}", request_type::COMMAND, user, commands::WHISPER, target, message),
+                ),
+                Err(error) => error,
+            }
+        }
+        ("GET", "/users", _) => match metered_lock(&gate.clients) {
+            Ok(clients) => ("200 OK", json!({ "users": clients.names() })),
+            Err(_) => ("500 Internal Server Error", json!({ "error": "Client list unavailable" })),
+        },
+        ("GET", "/messages", _) => {
+            let since = request
+                .query
+                .iter()
+                .flat_map(|query| query.split('&'))
+                .find_map(|pair| pair.strip_prefix("since="))
+                .map(|since| since.parse::<u64>());
+            let since = match since {
+                None => 0,
+                Some(Ok(since)) => since,
+                Some(Err(_)) => return ("400 Bad Request", json!({ "error": "since has to be a message id" })),
+            };
+            match history.lock() {
+                Ok(history) => ("200 OK", json!({ "messages": history.since(since) })),
+                Err(_) => ("500 Internal Server Error", json!({ "error": "History unavailable" })),
+            }
+        }
+        (_, "/messages", _) | (_, "/users", _) | (_, _, Some(_)) => {
+            ("405 Method Not Allowed", json!({ "error": "Method not allowed" }))
+        }
+        _ => ("404 Not Found", json!({ "error": "Not found" })),
+    }
+}
+
+/// # `message`
+/// The message of a JSON body, it has to be a single non-empty line
+fn message(request: &Request) -> Result<String, (&'static str, Value)> {
+    let body: PostBody = serde_json::from_slice(&request.body)
+        .map_err(|e| ("400 Bad Request", json!({ "error": format!("Invalid body: {}", e) })))?;
+    let message = body.message.trim();
+    if message.is_empty() || message.contains(['\r', '\n']) {
+        return Err(("400 Bad Request", json!({ "error": "message has to be a single non-empty line" })));
+    }
+    Ok(message.to_string())
+}
+
+/// # `forward`
+/// Hands a request to the request handling thread, the same way client requests get there
+fn forward(gate: &Gate, request: String) -> (&'static str, Value) {
+    match gate.requests.send(request) {
+        Ok(_) => ("202 Accepted", json!({ "status": "accepted" })),
+        Err(_) => ("503 Service Unavailable", json!({ "error": "Server is stopping" })),
+    }
+}
@@ -0,0 +1,522 @@
+use std::{
+    io::{self, BufReader, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use super::gate::Gate;
+use super::server::{read_request_line, RequestLine, ServerHandle};
+use super::tools::{metered_lock, ClientName, Connection, Presence};
+use super::{commands, request_type, resposne_type, server_log, LogMessagType};
+
+/// How the server calls itself in IRC prefixes
+const SERVER_NAME: &str = "azeezd-sockets";
+/// The one channel, it stands for the whole chat
+pub(crate) const CHANNEL: &str = "#chat";
+
+/// Numeric replies
+mod reply {
+    pub const WELCOME: &str = "001";
+    pub const YOUR_HOST: &str = "002";
+    pub const CREATED: &str = "003";
+    pub const MY_INFO: &str = "004";
+    pub const UNAWAY: &str = "305";
+    pub const NOW_AWAY: &str = "306";
+    pub const END_OF_WHO: &str = "315";
+    pub const CHANNEL_MODE: &str = "324";
+    pub const NAMES: &str = "353";
+    pub const END_OF_NAMES: &str = "366";
+    pub const NO_MOTD: &str = "422";
+    pub const NO_SUCH_CHANNEL: &str = "403";
+    pub const INPUT_TOO_LONG: &str = "417";
+    pub const UNKNOWN_COMMAND: &str = "421";
+    pub const NO_NICKNAME_GIVEN: &str = "431";
+    pub const ERRONEUS_NICKNAME: &str = "432";
+    pub const NICKNAME_IN_USE: &str = "433";
+    pub const NOT_REGISTERED: &str = "451";
+    pub const NEED_MORE_PARAMS: &str = "461";
+    pub const ALREADY_REGISTERED: &str = "462";
+}
+
+/// # `IrcConnection`
+/// The sending side of an IRC client. Turns the server's response lines into IRC lines
+pub(crate) struct IrcConnection {
+    socket: TcpStream,
+    nick: String,
+    /// Whether the client is in `CHANNEL`. Channel traffic is not sent after it parted
+    joined: Arc<AtomicBool>,
+    /// Whether the client is away or busy, so it is only told when that changes
+    away: bool,
+}
+
+impl IrcConnection {
+    /// # `send_line`
+    /// Translates a response line and sends the IRC lines it becomes, if any
+    pub(crate) fn send_line(&mut self, line: &str) -> io::Result<()> {
+        for line in self.translate(line) {
+            write!(self.socket, "{}\r\n", line)?;
+        }
+        Ok(())
+    }
+
+    /// # `close`
+    pub(crate) fn close(&mut self) {
+        let _ = self.socket.shutdown(Shutdown::Both);
+    }
+
+    /// # `translate`
+    /// The IRC lines a response line becomes
+    fn translate(&mut self, response: &str) -> Vec<String> {
+        let content = response.get(4..).unwrap_or_default();
+        let nick = &self.nick;
+        let joined = self.joined.load(Ordering::SeqCst);
+
+        match response.get(..3).unwrap_or_default() {
+            resposne_type::CONNECTION_ACCEPTED => vec![
+                numeric(reply::WELCOME, nick, &format!(":Welcome to the chat {}", nick)),
+                numeric(reply::YOUR_HOST, nick, &format!(":Your host is {}", SERVER_NAME)),
+                numeric(reply::CREATED, nick, ":This server speaks a small part of IRC"),
+                numeric(reply::MY_INFO, nick, &format!("{} 0 o nt", SERVER_NAME)),
+                numeric(reply::NO_MOTD, nick, ":MOTD follows as notices"),
+            ],
+            resposne_type::CONNECTION_DENIED => vec!["ERROR :Closing Link: You have been disconnected".to_string()],
+            resposne_type::PUBLIC_MESSAGE if joined => {
+                let (sender, message) = unstamped(content);
+                // IRC clients show their own messages themselves
+                if sender == nick {
+                    Vec::new()
+                } else {
+                    vec![format!("{} PRIVMSG {} :{}", user_prefix(sender), CHANNEL, message)]
+                }
+            }
+            resposne_type::REPLY if joined => {
+                // `<id> <time> <parent> <sender> <text>`, IRC has no replies so it becomes a plain message
+                let mut parts = content.splitn(5, ' ').skip(3);
+                let (sender, message) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+                if sender == nick {
+                    Vec::new()
+                } else {
+                    vec![format!("{} PRIVMSG {} :{}", user_prefix(sender), CHANNEL, message)]
+                }
+            }
+            resposne_type::THREAD_MESSAGE => {
+                let (depth, stamped) = content.split_once(' ').unwrap_or_default();
+                let (sender, message) = unstamped(stamped);
+                let indent = "  ".repeat(depth.parse().unwrap_or(0));
+                vec![format!(":{} NOTICE {} :{}<{}> {}", SERVER_NAME, nick, indent, sender, message)]
+            }
+            resposne_type::NICK_CHANGED => {
+                let (old, new) = content.split_once(' ').unwrap_or_default();
+                let own = old == self.nick;
+                if own {
+                    self.nick = new.to_string();
+                }
+                if own || joined {
+                    vec![format!("{} NICK :{}", user_prefix(old), new)]
+                } else {
+                    Vec::new()
+                }
+            }
+            resposne_type::PRESENCE => {
+                // Only changes of the client's own presence, IRC has no way to tell about others
+                let mut parts = content.split(' ');
+                let away = match (parts.next(), parts.next()) {
+                    (Some(name), Some(state)) if name == nick => state != Presence::Online.state(),
+                    _ => return Vec::new(),
+                };
+                match (away, std::mem::replace(&mut self.away, away)) {
+                    (true, false) => vec![numeric(reply::NOW_AWAY, nick, ":You have been marked as being away")],
+                    (false, true) => vec![numeric(reply::UNAWAY, nick, ":You are no longer marked as being away")],
+                    _ => Vec::new(),
+                }
+            }
+            resposne_type::MENTION => {
+                let (sender, message) = unstamped(content);
+                vec![format!(":{} NOTICE {} :{} mentioned you: {}", SERVER_NAME, nick, sender, message)]
+            }
+            resposne_type::PRIVATE_MESSAGE => {
+                let (sender, message) = unstamped(content);
+                vec![format!("{} PRIVMSG {} :{}", user_prefix(sender), nick, message)]
+            }
+            resposne_type::MESSAGE_EDITED if joined => {
+                let mut parts = content.splitn(3, ' ').skip(1);
+                let (sender, message) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+                vec![format!(":{} NOTICE {} :{} edited a message: {}", SERVER_NAME, CHANNEL, sender, message)]
+            }
+            resposne_type::USER_JOINED if joined => vec![format!("{} JOIN {}", user_prefix(content), CHANNEL)],
+            resposne_type::USER_LEFT if joined => vec![format!("{} QUIT :Left the server", user_prefix(content))],
+            resposne_type::SYSTEM | resposne_type::ERROR => vec![format!(":{} NOTICE {} :{}", SERVER_NAME, nick, content)],
+            resposne_type::USER_LIST => vec![
+                numeric(reply::NAMES, nick, &format!("= {} :{}", CHANNEL, content)),
+                numeric(reply::END_OF_NAMES, nick, &format!("{} :End of /NAMES list", CHANNEL)),
+            ],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// # `unstamped`
+/// Sender and text of a stamped message (`<id> <time> <sender> <text>`), IRC has no place for the stamp
+fn unstamped(content: &str) -> (&str, &str) {
+    let mut parts = content.splitn(4, ' ').skip(2);
+    (parts.next().unwrap_or_default(), parts.next().unwrap_or_default())
+}
+
+/// # `valid_nick`
+/// Whether a nick can be used on IRC, where some characters have a meaning of their own
+fn valid_nick(nick: &str) -> bool {
+    !nick.contains(|c: char| c.is_whitespace() || c == ':' || c == ',') && !nick.starts_with('#')
+}
+
+/// # `numeric`
+/// A numeric reply from the server to `nick`
+fn numeric(code: &str, nick: &str, parameters: &str) -> String {
+    format!(":{} {} {} {}", SERVER_NAME, code, nick, parameters)
+}
+
+/// # `user_prefix`
+/// The prefix of a line coming from `nick`
+fn user_prefix(nick: &str) -> String {
+    format!(":{}!{}@{}", nick, nick, SERVER_NAME)
+}
+
+/// # `serve_irc`
+/// Accepts IRC clients on `listener` from a background thread
+pub(crate) fn serve_irc(listener: TcpListener, gate: Gate, handle: ServerHandle) {
+    if let Ok(address) = listener.local_addr() {
+        server_log(format!("IRC listener on {}", address), LogMessagType::Information);
+    }
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            if !handle.is_running() {
+                break;
+            }
+            if let Ok(stream) = stream {
+                let gate = gate.clone();
+                thread::spawn(move || IrcSession::run(stream, gate));
+            }
+        }
+    });
+}
+
+/// # `IrcSession`
+/// The receiving side of an IRC client: registration and the commands it sends
+struct IrcSession {
+    gate: Gate,
+    writer: TcpStream,
+    address: SocketAddr,
+    nick: Option<String>,
+    user: bool,
+    /// Given with PASS before registering, operators join with it
+    password: Option<String>,
+    /// The name of the client in the chat once registered, follows nick changes
+    name: Option<ClientName>,
+    joined: Arc<AtomicBool>,
+}
+
+impl IrcSession {
+    /// # `run`
+    /// Reads IRC lines until the client quits or the connection ends
+    fn run(stream: TcpStream, gate: Gate) {
+        let address = match stream.peer_addr() {
+            Ok(address) => address,
+            Err(_) => return,
+        };
+        let (mut reader, writer) = match (stream.try_clone(), stream.try_clone()) {
+            (Ok(reader), Ok(writer)) => (BufReader::new(reader), writer),
+            _ => {
+                server_log(format!("Error cloning socket for client {}", address), LogMessagType::EncounteredError);
+                return;
+            }
+        };
+
+        let mut session = IrcSession {
+            gate,
+            writer,
+            address,
+            nick: None,
+            user: false,
+            password: None,
+            name: None,
+            joined: Arc::new(AtomicBool::new(false)),
+        };
+        loop {
+            let line = match read_request_line(&mut reader, session.gate.limits.line_length()) {
+                Ok(RequestLine::Line(line)) => line,
+                Ok(RequestLine::TooLong(_)) => {
+                    session.numeric(reply::INPUT_TOO_LONG, ":Input line was too long");
+                    continue;
+                }
+                Ok(RequestLine::Closed) => break,
+                Err(e) => {
+                    server_log(format!("Error \"{}\" reading from client {}. Closing thread", e, address), LogMessagType::EncounteredError);
+                    break;
+                }
+            };
+            if !session.handle_line(&line) {
+                break;
+            }
+        }
+        session.logout();
+    }
+
+    /// # `handle_line`
+    /// Handles one IRC line. Returns `false` once the connection should end
+    fn handle_line(&mut self, line: &str) -> bool {
+        let (command, parameters) = parse_line(line);
+        let command = command.to_ascii_uppercase();
+        let parameter = |i: usize| parameters.get(i).map(|parameter| parameter.as_str());
+
+        match command.as_str() {
+            "" | "PONG" | "NOTICE" => {}
+            "CAP" => {
+                // No capabilities, the client goes on with the registration
+                if parameter(0).is_some_and(|sub| sub.eq_ignore_ascii_case("LS")) {
+                    self.write(&format!(":{} CAP * LS :", SERVER_NAME));
+                }
+            }
+            "PING" => self.write(&format!(":{} PONG {} :{}", SERVER_NAME, SERVER_NAME, parameter(0).unwrap_or(SERVER_NAME))),
+            "QUIT" => {
+                self.write("ERROR :Closing Link: Quit");
+                return false;
+            }
+            "NICK" => match (parameter(0), self.name.is_some()) {
+                (None, _) => self.numeric(reply::NO_NICKNAME_GIVEN, ":No nickname given"),
+                (Some(nick), true) => {
+                    if valid_nick(nick) {
+                        self.request(request_type::COMMAND, &format!("{} {}", commands::NICK, nick));
+                    } else {
+                        self.numeric(reply::ERRONEUS_NICKNAME, &format!("{} :Erroneous nickname", nick));
+                    }
+                }
+                (Some(nick), false) => {
+                    self.nick = Some(nick.to_string());
+                    return self.register();
+                }
+            },
+            "PASS" => match (parameter(0), self.name.is_some()) {
+                (_, true) => self.numeric(reply::ALREADY_REGISTERED, ":You may not reregister"),
+                (None, false) => self.numeric(reply::NEED_MORE_PARAMS, "PASS :Not enough parameters"),
+                (Some(password), false) => self.password = Some(password.to_string()),
+            },
+            "USER" => {
+                if parameters.len() < 4 {
+                    self.numeric(reply::NEED_MORE_PARAMS, "USER :Not enough parameters");
+                } else if self.name.is_none() {
+                    self.user = true;
+                    return self.register();
+                }
+            }
+            _ if self.name.is_none() => self.numeric(reply::NOT_REGISTERED, ":You have not registered"),
+            "JOIN" => match parameter(0) {
+                None => self.numeric(reply::NEED_MORE_PARAMS, "JOIN :Not enough parameters"),
+                Some(channels) => {
+                    for channel in channels.split(',') {
+                        if channel.eq_ignore_ascii_case(CHANNEL) {
+                            self.join();
+                        } else {
+                            self.numeric(reply::NO_SUCH_CHANNEL, &format!("{} :Only {} exists", channel, CHANNEL));
+                        }
+                    }
+                }
+            },
+            "PART" => match parameter(0) {
+                Some(channel) if channel.eq_ignore_ascii_case(CHANNEL) => {
+                    if self.joined.swap(false, Ordering::SeqCst) {
+                        let name = self.name().unwrap_or_default();
+                        self.write(&format!("{} PART {}", user_prefix(&name), CHANNEL));
+                    }
+                }
+                Some(channel) => self.numeric(reply::NO_SUCH_CHANNEL, &format!("{} :No such channel", channel)),
+                None => self.numeric(reply::NEED_MORE_PARAMS, "PART :Not enough parameters"),
+            },
+            "PRIVMSG" => match (parameter(0), parameter(1)) {
+                (Some(target), Some(text)) if !text.is_empty() => {
+                    if target.eq_ignore_ascii_case(CHANNEL) {
+                        self.request(request_type::MESSAGE, text);
+                    } else {
+                        self.request(request_type::COMMAND, &format!("{} {} {}", commands::WHISPER, target, text));
+                    }
+                }
+                _ => self.numeric(reply::NEED_MORE_PARAMS, "PRIVMSG :Not enough parameters"),
+            },
+            "NAMES" => self.request(request_type::COMMAND, commands::WHO),
+            "AWAY" => match parameter(0).filter(|message| !message.is_empty()) {
+                Some(message) => self.request(request_type::COMMAND, &format!("{} {}", commands::AWAY, message)),
+                None => self.request(request_type::COMMAND, commands::BACK),
+            },
+            "MODE" => {
+                if parameter(0).is_some_and(|target| target.eq_ignore_ascii_case(CHANNEL)) {
+                    self.numeric(reply::CHANNEL_MODE, &format!("{} +nt", CHANNEL));
+                }
+            }
+            "WHO" => self.numeric(reply::END_OF_WHO, &format!("{} :End of /WHO list", parameter(0).unwrap_or("*"))),
+            _ => self.numeric(reply::UNKNOWN_COMMAND, &format!("{} :Unknown command", command)),
+        }
+        true
+    }
+
+    /// # `register`
+    /// Joins the chat once both NICK and USER were given. A taken nick can be replaced with another NICK,
+    /// any other denial ends the connection (returns `false`)
+    fn register(&mut self) -> bool {
+        let nick = match (&self.nick, self.user) {
+            (Some(nick), true) => nick.clone(),
+            _ => return true,
+        };
+        if !valid_nick(&nick) {
+            self.numeric(reply::ERRONEUS_NICKNAME, &format!("{} :Erroneous nickname", nick));
+            return true;
+        }
+        let taken = metered_lock(&self.gate.clients)
+            .map(|clients| clients.name_exists(&nick).is_some())
+            .unwrap_or(true);
+        if taken {
+            self.numeric(reply::NICKNAME_IN_USE, &format!("{} :Nickname is already in use", nick));
+            return true;
+        }
+
+        let socket = match self.writer.try_clone() {
+            Ok(socket) => socket,
+            Err(_) => return false,
+        };
+        // Everyone starts in the channel, the join is announced by the server like any other
+        self.joined.store(true, Ordering::SeqCst);
+        let connection = Connection::Irc(IrcConnection {
+            socket,
+            nick: nick.clone(),
+            joined: self.joined.clone(),
+            away: false,
+        });
+        let request = match &self.password {
+            Some(password) => format!("{} {} {}", request_type::CONNECT, nick, password),
+            None => format!("{} {}", request_type::CONNECT, nick),
+        };
+        match self.gate.admit(&request, Some(self.address), connection) {
+            Some(name) => {
+                self.name = Some(name);
+                self.request(request_type::COMMAND, commands::WHO);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// # `join`
+    /// Rejoins the channel after a PART, a JOIN while in it does nothing
+    fn join(&mut self) {
+        if !self.joined.swap(true, Ordering::SeqCst) {
+            let name = self.name().unwrap_or_default();
+            self.write(&format!("{} JOIN {}", user_prefix(&name), CHANNEL));
+            self.request(request_type::COMMAND, commands::WHO);
+        }
+    }
+
+    /// # `logout`
+    /// Leaves the chat if the client had joined it
+    fn logout(&mut self) {
+        if let Some(name) = self.name.take() {
+            let _ = self.gate.requests.send(format!("{} {} {}", request_type::COMMAND, name.get(), commands::LOGOUT));
+        }
+        let _ = self.writer.shutdown(Shutdown::Both);
+    }
+
+    /// # `request`
+    /// Sends a request for the registered client to the server's request handling
+    fn request(&self, identifier: &str, content: &str) {
+        if let Some(name) = self.name() {
+            if self.gate.requests.send(format!("{} {} {}", identifier, name, content)).is_err() {
+                server_log(format!("Error sending request from {} for handling", self.address), LogMessagType::EncounteredError);
+            }
+        }
+    }
+
+    /// # `write`
+    /// Writes a line to the client. The client list is held so the line does not land in the middle of one sent by another thread
+    fn write(&mut self, line: &str) {
+        if let Ok(_clients) = metered_lock(&self.gate.clients) {
+            let _ = write!(self.writer, "{}\r\n", line);
+        }
+    }
+
+    fn numeric(&mut self, code: &str, parameters: &str) {
+        // Until the client is registered replies go to `*`
+        let nick = self.name().unwrap_or_else(|| "*".to_string());
+        self.write(&numeric(code, &nick, parameters));
+    }
+
+    /// # `name`
+    /// The current name of the client, once registered
+    fn name(&self) -> Option<String> {
+        self.name.as_ref().map(ClientName::get)
+    }
+}
+
+/// # `parse_line`
+/// Splits an IRC line into its command and parameters. A prefix is skipped, a parameter starting with `:` takes the rest of the line
+fn parse_line(line: &str) -> (&str, Vec<String>) {
+    let line = match line.strip_prefix(':') {
+        Some(prefixed) => prefixed.split_once(' ').map(|(_, rest)| rest).unwrap_or(""),
+        None => line,
+    };
+    let (line, trailing) = match line.split_once(" :") {
+        Some((line, trailing)) => (line, Some(trailing)),
+        None => (line, None),
+    };
+
+    let mut words = line.split_ascii_whitespace();
+    let command = words.next().unwrap_or_default();
+    let mut parameters: Vec<String> = words.map(|word| word.to_string()).collect();
+    if let Some(trailing) = trailing {
+        parameters.push(trailing.to_string());
+    }
+    (command, parameters)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(parameters: &[&str]) -> Vec<String> {
+        parameters.iter().map(|parameter| parameter.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_line_splits_command_and_parameters() {
+        assert_eq!(parse_line("NICK azeez"), ("NICK", words(&["azeez"])));
+        assert_eq!(parse_line("USER azeez 0 * :Azeez D"), ("USER", words(&["azeez", "0", "*", "Azeez D"])));
+        assert_eq!(parse_line("PRIVMSG #chat :hi: there :)"), ("PRIVMSG", words(&["#chat", "hi: there :)"])));
+        assert_eq!(parse_line("QUIT"), ("QUIT", Vec::new()));
+        assert_eq!(parse_line(""), ("", Vec::new()));
+    }
+
+    #[test]
+    fn parse_line_skips_the_prefix() {
+        assert_eq!(parse_line(":azeez!azeez@host PRIVMSG bob :hi"), ("PRIVMSG", words(&["bob", "hi"])));
+        assert_eq!(parse_line(":azeez"), ("", Vec::new()));
+    }
+
+    #[test]
+    fn parse_line_keeps_an_empty_trailing_parameter() {
+        assert_eq!(parse_line("AWAY :"), ("AWAY", words(&[""])));
+    }
+
+    #[test]
+    fn unstamped_drops_id_and_time() {
+        assert_eq!(unstamped("3 2024-05-01T10:00:00Z azeez hello there"), ("azeez", "hello there"));
+        assert_eq!(unstamped("3 2024-05-01T10:00:00Z azeez"), ("azeez", ""));
+    }
+
+    #[test]
+    fn valid_nick_refuses_irc_syntax() {
+        assert!(valid_nick("azeez"));
+        assert!(!valid_nick("#chat"));
+        assert!(!valid_nick("a:b"));
+        assert!(!valid_nick("a,b"));
+        assert!(!valid_nick("a b"));
+    }
+}
@@ -1,30 +1,108 @@
+mod admin;
+mod bot;
+mod chat_client;
 mod client;
+mod command_registry;
+mod gate;
+mod hooks;
+mod http;
+mod irc;
 mod server;
 mod tools;
+mod transport;
+mod tui;
+mod websocket;
 
 pub mod request_type {
+    /// `CON <name> [password]`, the first request of a connection. The password is the operator password, other names do not need it
     pub const CONNECT: &str = "CON";
     pub const MESSAGE: &str = "MSG";
     pub const COMMAND: &str = "CMD";
+    /// `TYP <name> start|stop [target]`, the client started or stopped writing a message, to `target` if it is a whisper.
+    /// Optional, clients repeat `start` every few seconds while writing and others forget it if it is not repeated
+    pub const TYPING: &str = "TYP";
+    pub const TYPING_START: &str = "start";
+    pub const TYPING_STOP: &str = "stop";
+    /// A request may start with `@<id> ` to be acknowledged (`@7 MSG azeez hi`)
+    pub const ID_PREFIX: &str = "@";
 }
 
 pub mod resposne_type {
 
+    /// `MSG <id> <time> <sender> <text>`, the id and UTC time (RFC 3339) are given by the server
     pub const PUBLIC_MESSAGE: &str = super::request_type::MESSAGE;
+    /// `PRM <id> <time> <sender> <text>`, stamped like public messages
     pub const PRIVATE_MESSAGE: &str = "PRM";
     pub const CONNECTION_ACCEPTED: &str = "CAC";
     pub const CONNECTION_DENIED: &str = "CDE";
     pub const USER_JOINED: &str = "UJS";
     pub const USER_LEFT: &str = "ULS";
+    pub const SYSTEM: &str = "SYS";
+    pub const USER_LIST: &str = "ULI";
+    pub const ERROR: &str = "ERR";
+    /// `ACK <id>`, the request with that id was handled
+    pub const ACKNOWLEDGED: &str = "ACK";
+    /// `DLV <id> <name>`, the whisper with that id was written to `name`
+    pub const DELIVERED: &str = "DLV";
+    /// `NAK <id> <reason>`, the request with that id was rejected. Takes the place of `ERR` for requests with an id
+    pub const REJECTED: &str = "NAK";
+    /// `MED <id> <sender> <text>`, the public message `id` of `sender` now reads `text`
+    pub const MESSAGE_EDITED: &str = "MED";
+    /// `MDL <id>`, the public message `id` was deleted
+    pub const MESSAGE_DELETED: &str = "MDL";
+    /// `RAD <id> <user> <emoji>`, `user` reacted to the public message `id` with `emoji`
+    pub const REACTION_ADDED: &str = "RAD";
+    /// `RRM <id> <user> <emoji>`, `user` took their `emoji` reaction to the public message `id` back
+    pub const REACTION_REMOVED: &str = "RRM";
+    /// `RPL <id> <time> <parent> <sender> <text>`, a public message stamped like `MSG` that replies to the message `parent`
+    pub const REPLY: &str = "RPL";
+    /// `THR <depth> <id> <time> <sender> <text>`, one message of a thread asked for with `/thread`. The messages come in thread
+    /// order, each reply right after the message it replies to and nested one deeper
+    pub const THREAD_MESSAGE: &str = "THR";
+    /// `MEN <id> <time> <sender> <text>`, the public message `id` mentions this client. Sent after the message itself,
+    /// or when the client joins if it was offline
+    pub const MENTION: &str = "MEN";
+    /// `TYP <name> start|stop`, `name` started or stopped writing a message to everyone or to this client
+    pub const TYPING: &str = super::request_type::TYPING;
+    /// `PRS <name> <online|away|busy> <idle seconds> [message]`, the presence of `name`. Broadcast when it changes and
+    /// sent for everyone after the user list of `/who`
+    pub const PRESENCE: &str = "PRS";
+    /// `NCK <old> <new>`, the client `old` is now called `new`
+    pub const NICK_CHANGED: &str = "NCK";
 }
 
 pub mod commands {
     pub const WHISPER: &str = "/w";
     pub const LOGOUT: &str = "/exit";
-    pub const LIST: [&str; 2] = [WHISPER, LOGOUT];
+    pub const ANNOUNCE: &str = "/announce";
+    pub const WHO: &str = "/who";
+    pub const HELP: &str = "/help";
+    pub const EDIT: &str = "/edit";
+    pub const DELETE: &str = "/delete";
+    pub const REACT: &str = "/react";
+    pub const REPLY: &str = "/reply";
+    pub const THREAD: &str = "/thread";
+    pub const AWAY: &str = "/away";
+    pub const BUSY: &str = "/busy";
+    pub const BACK: &str = "/back";
+    pub const NICK: &str = "/nick";
 }
 
 /// CHANGE THIS IF YOU WANT ANOTHER HOST ADDRESS!!
 pub const SERVER_ADDRESS: &str = "localhost:8080";
 
-pub use self::{client::Client, server::Server, tools::{ClientList, server_log, LogMessagType}};
+pub use self::{
+    admin::{send_admin_command, DEFAULT_ADMIN_SOCKET},
+    bot::{Bot, BotContext, BotEvent, DiceBot},
+    chat_client::{ChatClient, ChatError, ChatEvent, PendingRequest, Reactions, TypingUsers, TYPING_INTERVAL, TYPING_TIMEOUT},
+    client::Client,
+    command_registry::{Command, CommandContext, CommandHandler, CommandRegistry},
+    hooks::ServerHooks,
+    server::{Limits, Server, ServerBuilder, ServerHandle, DEFAULT_MAX_LINE_LENGTH},
+    transport::{Address, Stream, UNIX_PREFIX},
+    tui::Tui,
+    tools::{
+        open_audit_log, parse_level, parse_time, serve_metrics, server_log, Announcement, ApiToken, AuditQuery, ClientList, Config, Presence,
+        LogFormat, LogMessagType, ServerLogger, DEFAULT_AUDIT_FILE,
+    },
+};
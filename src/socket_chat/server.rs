@@ -1,304 +1,1076 @@
 use std::{
-    io::{Read, Write},
+    io::{self, BufRead, BufReader},
     net::TcpListener,
+    path::{Path, PathBuf},
+    collections::{HashMap, HashSet, VecDeque},
     sync::{
-        mpsc::{self, TryRecvError},
-        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
     },
     thread,
+    time::{Duration, Instant},
 };
 
 use colored::*;
 
+use super::admin::{serve_admin, AdminContext};
+use super::bot::{run_bot, Bot};
+use super::command_registry::{disconnect, Command, CommandContext, CommandRegistry};
+use super::hooks::{HookList, ServerHooks};
 use super::request_type as rt;
+use super::gate::Gate;
+use super::http::serve_http;
+use super::irc::serve_irc;
+use super::websocket::serve_websocket;
+use super::tools::{metered_lock, parse_level, BanList, Config, Connection, History, Mentions, Stamp, METRICS};
+use super::transport::{describe_peer, Address, Listener, Stream};
 use super::{server_log, ClientList, LogMessagType};
 
+/// Longest request line read when no `max_message_length` is set, so a line that never ends can not fill the server's memory
+pub const DEFAULT_MAX_LINE_LENGTH: usize = 64 * 1024;
+/// How many request ids the server remembers to recognize retries, of every client together
+const RECENT_IDS: usize = 4096;
+/// Least time between two typing indicators of a client to the same recipients that are relayed
+const TYPING_THROTTLE: Duration = Duration::from_secs(2);
+
+/// # `Limits`
+/// Caps a server enforces. `None` means unlimited
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Limits {
+    /// Most clients connected at the same time, anyone joining beyond that is denied
+    pub max_clients: Option<usize>,
+    /// Longest request line in bytes, longer ones are rejected
+    pub max_message_length: Option<usize>,
+}
+
+impl Limits {
+    /// # `line_length`
+    /// Longest request line that is read, `max_message_length` or `DEFAULT_MAX_LINE_LENGTH` without one
+    pub fn line_length(&self) -> usize {
+        self.max_message_length.unwrap_or(DEFAULT_MAX_LINE_LENGTH)
+    }
+}
+
+/// # `RecentIds`
+/// The ids of the latest acknowledged requests, by sender. A request whose id is in here is a retry and is only acknowledged again.
+/// They are kept past logging out, a client that lost its connection sends its requests again after reconnecting. Instead the
+/// oldest id of anyone is forgotten once there are `RECENT_IDS`
+#[derive(Default)]
+struct RecentIds {
+    ids: HashSet<(String, String)>,
+    /// The same ids, oldest first
+    order: VecDeque<(String, String)>,
+}
+
+impl RecentIds {
+    /// # `contains`
+    fn contains(&self, sender: &str, id: &str) -> bool {
+        self.ids.contains(&(sender.to_string(), id.to_string()))
+    }
+
+    /// # `remember`
+    /// Adds `id` to the ids of `sender`, forgetting the oldest one once there are `RECENT_IDS`
+    fn remember(&mut self, sender: &str, id: &str) {
+        let key = (sender.to_string(), id.to_string());
+        if !self.ids.insert(key.clone()) {
+            return;
+        }
+        self.order.push_back(key);
+        if self.order.len() > RECENT_IDS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// # `TypingThrottle`
+/// When a `start` typing indicator of a client was last relayed, by client and whisper target. Only clients that are online
+/// and write to everyone or to someone online are in here
+struct TypingThrottle {
+    relayed: HashMap<(String, Option<String>), Instant>,
+    throttle: Duration,
+}
+
+impl TypingThrottle {
+    /// # `new`
+    /// Relays a `start` at most every `throttle`
+    fn new(throttle: Duration) -> TypingThrottle {
+        TypingThrottle { relayed: HashMap::new(), throttle }
+    }
+
+    /// # `start`
+    /// Whether a `start` of `sender` to `target` is relayed, not if one was relayed within the throttle
+    fn start(&mut self, sender: &str, target: Option<&str>) -> bool {
+        let key = (sender.to_string(), target.map(|target| target.to_string()));
+        match self.relayed.get(&key) {
+            Some(relayed) if relayed.elapsed() < self.throttle => false,
+            _ => {
+                self.relayed.insert(key, Instant::now());
+                true
+            }
+        }
+    }
+
+    /// # `stop`
+    /// Whether a `stop` of `sender` to `target` is relayed, only if the `start` before it was
+    fn stop(&mut self, sender: &str, target: Option<&str>) -> bool {
+        self.relayed
+            .remove(&(sender.to_string(), target.map(|target| target.to_string())))
+            .is_some()
+    }
+
+    /// # `forget_offline`
+    /// Forgets the indicators of clients that left or changed their name, and those to whisper targets that did
+    fn forget_offline(&mut self, clients: &ClientList) {
+        let online = |name: &String| clients.name_exists(name).is_some();
+        self.relayed
+            .retain(|(sender, target), _| online(sender) && target.as_ref().is_none_or(online));
+    }
+}
+
+/// # `split_request_id`
+/// Splits the id off a request line (`@<id> MSG ...`). Requests without one are returned as they are
+pub(crate) fn split_request_id(line: &str) -> (Option<&str>, &str) {
+    match line.strip_prefix(rt::ID_PREFIX).and_then(|rest| rest.split_once(' ')) {
+        Some((id, request)) => (Some(id), request),
+        None => (None, line),
+    }
+}
+
+/// # `RequestLine`
+/// What `read_request_line` read
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum RequestLine {
+    /// A whole request, without its line ending
+    Line(String),
+    /// A request longer than allowed. Only its start was kept, enough to tell who sent it and its id
+    TooLong(String),
+    /// The connection was closed
+    Closed,
+}
+
+/// # `read_request_line`
+/// Reads one newline terminated request of at most `max` bytes. Bytes that are not UTF-8 are replaced instead of failing the read,
+/// the way the server always read requests. The rest of a longer request is skipped without keeping it in memory
+pub(crate) fn read_request_line(reader: &mut impl BufRead, max: usize) -> io::Result<RequestLine> {
+    let mut line = Vec::new();
+    // Room for the line ending, a request of exactly `max` bytes still fits
+    if io::Read::take(&mut *reader, max as u64 + 2).read_until(b'\n', &mut line)? == 0 {
+        return Ok(RequestLine::Closed);
+    }
+    let ended = line.ends_with(b"\n");
+    if !ended && line.len() as u64 == max as u64 + 2 {
+        skip_line(reader)?;
+    }
+    let content = line.strip_suffix(b"\n").unwrap_or(&line);
+    let content = content.strip_suffix(b"\r").unwrap_or(content);
+    let request = String::from_utf8_lossy(content).trim_end().to_string();
+    if content.len() > max {
+        return Ok(RequestLine::TooLong(request));
+    }
+    Ok(RequestLine::Line(request))
+}
+
+/// # `skip_line`
+/// Reads up to and including the next newline and throws it away
+fn skip_line(reader: &mut impl BufRead) -> io::Result<()> {
+    loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        match buffer.iter().position(|byte| *byte == b'\n') {
+            Some(end) => {
+                reader.consume(end + 1);
+                return Ok(());
+            }
+            None => {
+                let length = buffer.len();
+                reader.consume(length);
+            }
+        }
+    }
+}
+
+/// # `Gateway`
+/// Protocols the server speaks next to its own line protocol, each on its own listener
+enum Gateway {
+    WebSocket,
+    Irc,
+    Http,
+}
+
+/// # `ServerBuilder`
+/// Sets up a `Server` before it is bound: addresses, limits, config and hooks. Created with `Server::builder`
+pub struct ServerBuilder {
+    addresses: Vec<String>,
+    limits: Limits,
+    config: Config,
+    commands: CommandRegistry,
+    hooks: Vec<Box<dyn ServerHooks>>,
+    bots: Vec<Box<dyn Bot>>,
+    gateways: Vec<(Gateway, String)>,
+}
+
+impl ServerBuilder {
+    /// # `address`
+    /// Address to listen on, `SERVER_ADDRESS` by default. Either `host:port` or `unix:<path>` for a Unix domain socket.
+    /// Port `0` picks a free port, see `ServerHandle::address`. Replaces every address given before
+    pub fn address(mut self, address: &str) -> ServerBuilder {
+        self.addresses = vec![address.to_string()];
+        self
+    }
+
+    /// # `also_address`
+    /// Listens on `address` as well, such as `[::]:8080` next to `0.0.0.0:8080`. Every address gets its own accept loop
+    pub fn also_address(mut self, address: &str) -> ServerBuilder {
+        self.addresses.push(address.to_string());
+        self
+    }
+
+    /// # `websocket`
+    /// Also accepts WebSocket clients on `address`, see `Server::listen_websocket`
+    pub fn websocket(mut self, address: &str) -> ServerBuilder {
+        self.gateways.push((Gateway::WebSocket, address.to_string()));
+        self
+    }
+
+    /// # `irc`
+    /// Also accepts IRC clients on `address`, see `Server::listen_irc`
+    pub fn irc(mut self, address: &str) -> ServerBuilder {
+        self.gateways.push((Gateway::Irc, address.to_string()));
+        self
+    }
+
+    /// # `http`
+    /// Also answers the HTTP API on `address`, see `Server::listen_http`
+    pub fn http(mut self, address: &str) -> ServerBuilder {
+        self.gateways.push((Gateway::Http, address.to_string()));
+        self
+    }
+
+    /// # `max_clients`
+    /// Denies new connections while `max_clients` clients are connected
+    pub fn max_clients(mut self, max_clients: usize) -> ServerBuilder {
+        self.limits.max_clients = Some(max_clients);
+        self
+    }
+
+    /// # `max_message_length`
+    /// Rejects requests longer than `max_message_length` bytes. Without it connections still stop reading at `DEFAULT_MAX_LINE_LENGTH`
+    pub fn max_message_length(mut self, max_message_length: usize) -> ServerBuilder {
+        self.limits.max_message_length = Some(max_message_length);
+        self
+    }
+
+    /// # `config`
+    /// Starts the server with `config` instead of the default one
+    pub fn config(mut self, config: Config) -> ServerBuilder {
+        self.config = config;
+        self
+    }
+
+    /// # `command`
+    /// Adds a command to the server's registry, replacing a built-in one with the same name
+    pub fn command(mut self, command: Command) -> ServerBuilder {
+        self.commands.register(command);
+        self
+    }
+
+    /// # `commands`
+    /// Replaces the whole command registry, `CommandRegistry::empty()` leaves clients without any commands. `/exit` is handled
+    /// by the server and works whatever the registry holds
+    pub fn commands(mut self, commands: CommandRegistry) -> ServerBuilder {
+        self.commands = commands;
+        self
+    }
+
+    /// # `hook`
+    /// Adds hooks that are called on connections, messages and commands. Hooks run in the order they were added
+    pub fn hook<H: ServerHooks + 'static>(mut self, hook: H) -> ServerBuilder {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    /// # `bot`
+    /// Adds a bot the server hosts, see `Server::add_bot`
+    pub fn bot<B: Bot + 'static>(mut self, bot: B) -> ServerBuilder {
+        self.bots.push(Box::new(bot));
+        self
+    }
+
+    /// # `build`
+    /// Binds the addresses and returns the `Server`. Nothing is accepted until `Server::init` is called.
+    /// Every binding is logged. Addresses that could not be bound are skipped, `Err` is only returned if none could
+    pub fn build(self) -> io::Result<Server> {
+        let mut server_sockets = Vec::new();
+        let mut addresses = Vec::new();
+        let mut failure = None;
+        for address in self.addresses.iter() {
+            match Listener::bind(address).and_then(|listener| Ok((listener.local_addr()?, listener))) {
+                Ok((bound, listener)) => {
+                    server_log(format!("Listening on {}", bound), LogMessagType::Information);
+                    addresses.push(bound);
+                    server_sockets.push(listener);
+                }
+                Err(e) => {
+                    server_log(format!("Error \"{}\" listening on {}", e, address), LogMessagType::EncounteredError);
+                    failure = Some(e);
+                }
+            }
+        }
+        if server_sockets.is_empty() {
+            return Err(failure.unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No address to listen on")));
+        }
+        let address = addresses[0].clone();
+        let bans = Arc::new(Mutex::new(BanList::new()));
+        let config = Arc::new(Mutex::new(Config::default()));
+        Server::apply_config(self.config, &config, &bans);
+
+        let mut server = Server {
+            clients: Arc::new(Mutex::new(ClientList::new())),
+            bans,
+            config,
+            config_path: None,
+            limits: self.limits,
+            commands: Arc::new(self.commands),
+            hooks: Arc::new(self.hooks),
+            bots: self.bots,
+            history: Arc::new(Mutex::new(History::default())),
+            mentions: Arc::new(Mutex::new(Mentions::new())),
+            handle: ServerHandle {
+                running: Arc::new(AtomicBool::new(true)),
+                address,
+                listeners: Arc::new(Mutex::new(addresses)),
+            },
+            server_sockets,
+            gateways: Vec::new(),
+        };
+        for (gateway, address) in self.gateways {
+            server.listen(gateway, &address)?;
+        }
+        Ok(server)
+    }
+}
+
+/// # `ServerHandle`
+/// Stops a running `Server` from another thread. Cheap to clone
+#[derive(Clone)]
+pub struct ServerHandle {
+    running: Arc<AtomicBool>,
+    address: Address,
+    /// Addresses of every listener, they are woken up when stopping
+    listeners: Arc<Mutex<Vec<Address>>>,
+}
+
+impl ServerHandle {
+    /// # `address`
+    /// The first address the server is listening on
+    pub fn address(&self) -> Address {
+        self.address.clone()
+    }
+
+    /// # `stop`
+    /// Makes `Server::init` disconnect every client and return
+    pub fn stop(&self) {
+        if self.running.swap(false, Ordering::SeqCst) {
+            // Wake the accept loops up so they see the flag
+            if let Ok(listeners) = self.listeners.lock() {
+                for address in listeners.iter() {
+                    let _ = Stream::connect(&address.to_string());
+                }
+            }
+        }
+    }
+
+    /// # `is_running`
+    /// Whether the server has not been stopped yet
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+}
+
 /// # `Server`
 /// Chat server struct that handles the hosting and requests of clients
 pub struct Server {
     clients: Arc<Mutex<ClientList>>,
-    server_socket: TcpListener,
+    bans: Arc<Mutex<BanList>>,
+    config: Arc<Mutex<Config>>,
+    config_path: Option<PathBuf>,
+    limits: Limits,
+    commands: Arc<CommandRegistry>,
+    hooks: HookList,
+    bots: Vec<Box<dyn Bot>>,
+    /// Latest public messages, read through the HTTP API
+    history: Arc<Mutex<History>>,
+    /// Mentions waiting for offline clients
+    mentions: Arc<Mutex<Mentions>>,
+    handle: ServerHandle,
+    server_sockets: Vec<Listener>,
+    gateways: Vec<(Gateway, TcpListener)>,
 }
 
 impl Server {
     /// # `new`
-    /// Create a new server. Returns `Option<Server>` where None is if an error was encountered while binding the `TcpListener` to the address.
+    /// Create a new server on `SERVER_ADDRESS`. Returns `Option<Server>` where None is if an error was encountered while binding to the address.
     pub fn new() -> Option<Server> {
-        if let Ok(server) = TcpListener::bind(super::SERVER_ADDRESS) {
-            println!("{}", "Server Started!".bold().bright_green());
-
-            Some(Server {
-                clients: Arc::new(Mutex::new(ClientList::new())),
-                server_socket: server,
-            })
-        } else {
-            server_log(
-                "Error starting server".to_string(),
-                LogMessagType::EncounteredError,
-            );
-            None
-        }
+        Self::bind(&[super::SERVER_ADDRESS])
     }
 
-    /// # `init`
-    /// Initializes the server. Will block until the server is closed.
-    pub fn init(&mut self) {
-        // Server socket
-        let _server_socket = match self.server_socket.try_clone() {
-            Ok(val) => val,
-            Err(_) => {
+    /// # `bind`
+    /// Create a new server listening on every address in `addresses`, each `host:port` or `unix:<path>`.
+    /// Returns `None` if none of them could be bound
+    pub fn bind(addresses: &[&str]) -> Option<Server> {
+        let (first, rest) = addresses.split_first()?;
+        let builder = rest
+            .iter()
+            .fold(Self::builder().address(first), |builder, address| builder.also_address(address));
+        match builder.build() {
+            Ok(server) => {
+                println!("{}", "Server Started!".bold().bright_green());
+                Some(server)
+            }
+            Err(e) => {
                 server_log(
-                    "Error acquiring server socket. Closing Server...".to_string(),
+                    format!("Error \"{}\" starting server", e),
                     LogMessagType::EncounteredError,
                 );
-                std::process::exit(1);
+                None
             }
-        };
+        }
+    }
+
+    /// # `builder`
+    /// Returns a `ServerBuilder` with the default address, no limits, the default config, the built-in commands and no hooks or bots
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder {
+            addresses: vec![super::SERVER_ADDRESS.to_string()],
+            limits: Limits::default(),
+            config: Config::default(),
+            commands: CommandRegistry::default(),
+            hooks: Vec::new(),
+            bots: Vec::new(),
+            gateways: Vec::new(),
+        }
+    }
+
+    /// # `listen_websocket`
+    /// Binds `address` for browser clients. They upgrade to WebSocket and then speak the same protocol as TCP clients,
+    /// one or more request lines per text frame, and end up in the same client list. Returns `Err` if the address could not be bound
+    pub fn listen_websocket(&mut self, address: &str) -> io::Result<()> {
+        self.listen(Gateway::WebSocket, address)
+    }
+
+    /// # `listen_irc`
+    /// Binds `address` for IRC clients. They speak a part of RFC 1459/2812 (NICK, USER, JOIN, PART, PRIVMSG, QUIT, PING/PONG, NAMES)
+    /// and share the chat with everyone else as the channel `#chat`. Returns `Err` if the address could not be bound
+    pub fn listen_irc(&mut self, address: &str) -> io::Result<()> {
+        self.listen(Gateway::Irc, address)
+    }
+
+    /// # `listen_http`
+    /// Binds `address` for the HTTP API: `POST /messages`, `POST /whisper/{user}`, `GET /users` and `GET /messages?since=<id>`.
+    /// Callers authenticate with a bearer token from the config's `api_tokens`. Returns `Err` if the address could not be bound
+    pub fn listen_http(&mut self, address: &str) -> io::Result<()> {
+        self.listen(Gateway::Http, address)
+    }
+
+    fn listen(&mut self, gateway: Gateway, address: &str) -> io::Result<()> {
+        let listener = TcpListener::bind(address)?;
+        if let Ok(mut listeners) = self.handle.listeners.lock() {
+            listeners.push(Address::Tcp(listener.local_addr()?));
+        }
+        self.gateways.push((gateway, listener));
+        Ok(())
+    }
+
+    /// # `add_bot`
+    /// Adds a bot to host. It joins under its name when `init` is called, before anyone else can take that name
+    pub fn add_bot<B: Bot + 'static>(&mut self, bot: B) {
+        self.bots.push(Box::new(bot));
+    }
+
+    /// # `handle`
+    /// Returns a handle that stops the server from another thread
+    pub fn handle(&self) -> ServerHandle {
+        self.handle.clone()
+    }
+
+    /// # `load_config`
+    /// Takes the path of a config file as `PathBuf`, reads it and applies it. The same file is read again when the admin socket asks for a reload.
+    /// Returns `Err` with the reason if the file could not be read
+    pub fn load_config(&mut self, path: PathBuf) -> Result<(), String> {
+        Self::apply_config(Config::load(&path)?, &self.config, &self.bans);
+        self.config_path = Some(path);
+        Ok(())
+    }
+
+    /// # `apply_config`
+    /// Makes `config` the server's current config and applies the log threshold and the bans. Bans are only ever added, use the admin socket to lift them
+    pub fn apply_config(config: Config, current: &Arc<Mutex<Config>>, bans: &Arc<Mutex<BanList>>) {
+        if let Some(level) = config.log_level.as_deref().and_then(parse_level) {
+            log::set_max_level(level);
+        }
+        if let Ok(mut bans) = bans.lock() {
+            for name in config.banned_names.iter() {
+                bans.ban_name(name.clone());
+            }
+            for address in config.banned_addresses.iter() {
+                bans.ban_address(*address);
+            }
+        }
+        if let Ok(mut current) = current.lock() {
+            *current = config;
+        }
+    }
+
+    /// # `serve_admin`
+    /// Opens the admin control socket at `path` and answers admin commands on it from a background thread.
+    /// Returns `Err` if the socket could not be created
+    pub fn serve_admin(&self, path: &Path) -> io::Result<()> {
+        serve_admin(
+            path,
+            AdminContext {
+                clients: self.clients.clone(),
+                bans: self.bans.clone(),
+                config: self.config.clone(),
+                config_path: self.config_path.clone(),
+                hooks: self.hooks.clone(),
+                handle: self.handle.clone(),
+            },
+        )?;
+        // Stopping wakes the admin socket up like every other listener
+        if let Ok(mut listeners) = self.handle.listeners.lock() {
+            listeners.push(Address::Unix(path.to_path_buf()));
+        }
+        Ok(())
+    }
 
+    /// # `init`
+    /// Initializes the server. Will block until the server is stopped through its `ServerHandle`.
+    pub fn init(&mut self) {
         // Thread-communication channels
         let (sender, receiver) = mpsc::channel::<String>();
 
-        // Get reference of clients
-        let _clients = self.clients.clone();
+        // Get reference of clients, bans, config, commands, hooks, history and mentions
+        let mut dispatcher = Dispatcher {
+            clients: self.clients.clone(),
+            bans: self.bans.clone(),
+            config: self.config.clone(),
+            commands: self.commands.clone(),
+            hooks: self.hooks.clone(),
+            history: self.history.clone(),
+            mentions: self.mentions.clone(),
+            recent: RecentIds::default(),
+            typing: TypingThrottle::new(TYPING_THROTTLE),
+            limits: self.limits,
+        };
 
         // == REQUEST HANDLING THREAD ==
-        thread::spawn(move || loop {
-            match receiver.try_recv() {
-                Ok(msg) => {
-                    server_log(format!("{}", msg), LogMessagType::IncomingRequest);
-                    Self::handle_request(_clients.clone(), &msg);
-                }
-                Err(TryRecvError::Empty) => {}
-                Err(TryRecvError::Disconnected) => {
-                    server_log(
-                        "Sender channel is closed.".to_string(),
-                        LogMessagType::EncounteredError,
-                    );
-                }
+        // Ends once every sender is gone, which is after the server stopped and all client threads ended
+        thread::spawn(move || {
+            for msg in receiver {
+                server_log(msg.clone(), LogMessagType::IncomingRequest);
+                dispatcher.handle_request(&msg);
             }
         });
 
+        // == BOTS ==
+        let bots = std::mem::take(&mut self.bots);
+        let bot_names: Arc<HashSet<String>> = Arc::new(bots.iter().map(|bot| bot.name().to_string()).collect());
+        for bot in bots {
+            let (responses, receiver) = mpsc::channel::<String>();
+            let name = bot.name().to_string();
+            let added = metered_lock(&self.clients)
+                .map_err(|e| e.to_string())
+                .and_then(|mut clients| clients.add_bot(name.clone(), responses).map_err(|e| e.to_string()));
+            match added {
+                Ok(_) => {
+                    server_log(format!("Bot {} joined the server", name), LogMessagType::Information);
+                    run_bot(bot, receiver, sender.clone(), bot_names.clone());
+                }
+                Err(e) => server_log(format!("Error \"{}\" adding bot {}", e, name), LogMessagType::EncounteredError),
+            }
+        }
+
+        // == ANNOUNCEMENT THREAD ==
+        let _clients = self.clients.clone();
+        let _config = self.config.clone();
+        let handle = self.handle.clone();
+        thread::spawn(move || Self::announce_periodically(_clients, _config, handle));
+
+        let gate = Gate {
+            clients: self.clients.clone(),
+            bans: self.bans.clone(),
+            config: self.config.clone(),
+            hooks: self.hooks.clone(),
+            limits: self.limits,
+            mentions: self.mentions.clone(),
+            requests: sender,
+        };
+
+        // == GATEWAYS ==
+        for (gateway, listener) in std::mem::take(&mut self.gateways) {
+            match gateway {
+                Gateway::WebSocket => serve_websocket(listener, gate.clone(), self.handle.clone()),
+                Gateway::Irc => serve_irc(listener, gate.clone(), self.handle.clone()),
+                Gateway::Http => serve_http(listener, gate.clone(), self.history.clone(), self.handle.clone()),
+            }
+        }
+
         // == NEW CONNECTION LISTENING ==
-        loop {
-            match _server_socket.accept() {
-                Ok(connected) => {
-                    let mut buffer = [0; 1024];
-                    let mut client_socket = connected.0;
-
-                    // Get the connection requst from client (CON <name>) and handle it
-                    if let Err(_) = client_socket.read(&mut buffer) {
-                        server_log(
-                            format!(
-                                "Error reading connection request message from client {}",
-                                connected.1.to_string()
-                            ),
-                            LogMessagType::EncounteredError,
-                        );
-                    }
+        // Every listener has its own accept loop, they all let clients in through the same gate
+        thread::scope(|scope| {
+            for listener in self.server_sockets.iter() {
+                let gate = &gate;
+                let handle = &self.handle;
+                scope.spawn(move || Self::accept_clients(listener, gate, handle));
+            }
+        });
 
-                    let request = String::from_utf8_lossy(&buffer)
-                        .trim_end_matches('\u{0}')
-                        .to_string();
-
-                    // Confirm it is the CON request
-                    match &request[..3] {
-                        super::request_type::CONNECT => {
-                            // Cloned to use in separate thread
-                            let mut _socket = match client_socket.try_clone() {
-                                Ok(val) => val,
-                                Err(_) => {
-                                    format!("{}", connected.1.to_string()).to_string();
-                                    server_log(
-                                        format!(
-                                            "Error cloning socket for client {}",
-                                            connected.1.to_string()
-                                        ),
-                                        LogMessagType::EncounteredError,
-                                    );
-                                    continue;
-                                }
-                            };
-
-                            // Acquire client list
-                            let mut _clients = match self.clients.lock() {
-                                Ok(val) => val,
-                                Err(e) => {
-                                    server_log(
-                                        format!("Error \"{}\" acquiring client list from server", e),
-                                        LogMessagType::EncounteredError,
-                                    );
-                                    continue;
-                                }
-                            };
-
-                            // Error adding name to client list. Usually means name already exists
-                            if let Err(_) = _clients.add(request[4..].to_string(), client_socket) {
-                                server_log(
-                                    format!(
-                                        "Denied access for {} while adding them to client list",
-                                        connected.1.to_string()
-                                    ),
-                                    LogMessagType::Information,
-                                );
-                                _socket.write(super::resposne_type::CONNECTION_DENIED.as_bytes()); // ACCESS DENIED!!!!!!!!!!!!!!!!!!!!!!!!!!
-                            } else {
-                                // Connection OK!
-                                // Tell the client
-                                if let Err(_) = _socket
-                                    .write(super::resposne_type::CONNECTION_ACCEPTED.as_bytes())
-                                {
-                                    server_log(
-                                        format!(
-                                            "Error writing connection acceptance message to {}",
-                                            connected.1.to_string()
-                                        ),
-                                        LogMessagType::Information,
-                                    );
-                                }
-                                // Tell other clients
-                                _clients.send_to_all(&format!(
-                                    "{} {}",
-                                    super::resposne_type::USER_JOINED,
-                                    request[4..].to_string()
-                                ));
-                                server_log(
-                                    format!(
-                                        "Client {} [{}] joined the server",
-                                        request[4..].to_string(),
-                                        connected.1.to_string()
-                                    ),
-                                    LogMessagType::Information,
-                                );
-
-                                // Open thread for client
-                                let _sender = sender.clone();
-                                thread::spawn(move || loop {
-                                    let mut buffer = [0; 1024];
-                                    if let Err(e) = _socket.read(&mut buffer) {
-                                        server_log(format!("Error \"{}\" reading from client {}. Closing thread", e, connected.1.to_string()), LogMessagType::EncounteredError);
-                                        _sender.send(format!("{} {} {}", super::request_type::COMMAND, request[4..].to_string(), super::commands::LOGOUT));
-                                        break;
-                                    }
-                                    let request = String::from_utf8_lossy(&mut buffer).to_string();
-                                    if let Err(_) = _sender.send(request) {
-                                        server_log(
-                                            format!(
-                                                "Error sending request from {} for handling",
-                                                connected.1.to_string()
-                                            ),
-                                            LogMessagType::EncounteredError,
-                                        );
-                                    }
-                                });
-                            }
-                        }
-                        _ => {
-                            server_log(
-                                format!("Client {} sent invalid request", connected.1.to_string())
-                                    .to_string(),
-                                LogMessagType::Information,
-                            );
-                        }
+        // == STOPPED ==
+        // Disconnecting everyone ends their threads, which lets the request handling thread end too
+        if let Ok(mut clients) = metered_lock(&self.clients) {
+            for client in clients.list() {
+                if clients.kick(&client.name).is_ok() {
+                    for hook in self.hooks.iter() {
+                        hook.on_disconnect(&client.name);
                     }
                 }
-                _ => {}
             }
         }
+        server_log("Server stopped".to_string(), LogMessagType::Information);
     }
 
-    fn handle_request(clients: Arc<Mutex<ClientList>>, request: &String) {
+    /// # `accept_clients`
+    /// Accepts clients on `listener` until the server is stopped. Each one gets its own thread right away, so a client that
+    /// never sends its connection request does not hold up the others or the stop
+    fn accept_clients(listener: &Listener, gate: &Gate, handle: &ServerHandle) {
+        for connected in listener.incoming() {
+            if !handle.is_running() {
+                break;
+            }
+            if let Ok(client_socket) = connected {
+                let gate = gate.clone();
+                let handle = handle.clone();
+                thread::spawn(move || Self::serve_client(client_socket, gate, handle));
+            }
+        }
+    }
+
+    /// # `serve_client`
+    /// Reads the connection request of a client, lets it through the gate and forwards its requests until it disconnects
+    fn serve_client(client_socket: Stream, gate: Gate, handle: ServerHandle) {
+        let address = client_socket.peer_addr();
+        let peer = describe_peer(address);
+
+        // Requests are newline terminated, the reader is kept for the rest of the connection so nothing it buffered gets lost
+        let (mut reader, _socket) = match (client_socket.try_clone(), client_socket.try_clone()) {
+            (Ok(reader), Ok(socket)) => (BufReader::new(reader), socket),
+            _ => {
+                server_log(
+                    format!("Error cloning socket for client {}", peer),
+                    LogMessagType::EncounteredError,
+                );
+                return;
+            }
+        };
+
+        // Get the connection requst from client (CON <name>) and handle it
+        let request = match read_request_line(&mut reader, gate.limits.line_length()) {
+            Ok(RequestLine::Line(request)) => request,
+            // Not a valid connection request, the gate turns it away
+            Ok(RequestLine::TooLong(_)) | Ok(RequestLine::Closed) => String::new(),
+            Err(_) => {
+                server_log(
+                    format!(
+                        "Error reading connection request message from client {}",
+                        peer
+                    ),
+                    LogMessagType::EncounteredError,
+                );
+                String::new()
+            }
+        };
+        // The server may have stopped while it waited for the request
+        if !handle.is_running() {
+            return;
+        }
+
+        let name = match gate.admit(&request, address, Connection::Socket(_socket)) {
+            Some(name) => name,
+            None => return,
+        };
+        let _sender = gate.requests.clone();
+        loop {
+            let request = match read_request_line(&mut reader, gate.limits.line_length()) {
+                Ok(RequestLine::Line(request)) => request,
+                Ok(RequestLine::TooLong(start)) => {
+                    gate.reject_too_long(&name, &start);
+                    continue;
+                }
+                Ok(RequestLine::Closed) => { // Connection closed by the client
+                    let _ = _sender.send(format!("{} {} {}", super::request_type::COMMAND, name.get(), super::commands::LOGOUT));
+                    break;
+                }
+                Err(e) => {
+                    server_log(format!("Error \"{}\" reading from client {}. Closing thread", e, peer), LogMessagType::EncounteredError);
+                    let _ = _sender.send(format!("{} {} {}", super::request_type::COMMAND, name.get(), super::commands::LOGOUT));
+                    break;
+                }
+            };
+            if request.is_empty() {
+                continue;
+            }
+            if !gate.forward(&name, &request) {
+                server_log(
+                    format!(
+                        "Error sending request from {} for handling",
+                        peer
+                    ),
+                    LogMessagType::EncounteredError,
+                );
+            }
+        }
+    }
+
+    /// # `announce_periodically`
+    /// Broadcasts the announcements of the current config, each one every `every_secs` seconds. Picks up config reloads on its own
+    fn announce_periodically(clients: Arc<Mutex<ClientList>>, config: Arc<Mutex<Config>>, handle: ServerHandle) {
+        let mut elapsed: u64 = 0;
+        while handle.is_running() {
+            thread::sleep(Duration::from_secs(1));
+            elapsed += 1;
+
+            let due: Vec<String> = match config.lock() {
+                Ok(config) => config
+                    .announcements
+                    .iter()
+                    .filter(|announcement| announcement.every_secs > 0 && elapsed.is_multiple_of(announcement.every_secs))
+                    .map(|announcement| announcement.message.clone())
+                    .collect(),
+                Err(_) => continue,
+            };
+            if due.is_empty() {
+                continue;
+            }
+
+            if let Ok(mut clients) = metered_lock(&clients) {
+                for message in due {
+                    clients.send_to_all(&format!("{} {}", super::resposne_type::SYSTEM, message));
+                }
+            }
+        }
+    }
+
+    /// # `reject`
+    /// Tells `sender` why their request was not handled with an error response, or a rejection of `id` if the request had one
+    pub(crate) fn reject(clients: &mut ClientList, sender: &str, id: Option<&str>, reason: &str) {
+        server_log(format!("Rejected request from {} \"{}\"", sender, reason), LogMessagType::Information);
+        let response = match id {
+            Some(id) => format!("{} {} {}", super::resposne_type::REJECTED, id, reason),
+            None => format!("{} {}", super::resposne_type::ERROR, reason),
+        };
+        let _ = clients.send_to(&sender.to_string(), &response);
+    }
+}
+
+/// # `Dispatcher`
+/// Everything the request handling thread needs. Requests from every listener, bot and gateway end up here, one at a time.
+/// The client list is held for the whole request, anything else that locks it and the bans, config or history locks the client list first
+struct Dispatcher {
+    clients: Arc<Mutex<ClientList>>,
+    bans: Arc<Mutex<BanList>>,
+    config: Arc<Mutex<Config>>,
+    commands: Arc<CommandRegistry>,
+    hooks: HookList,
+    history: Arc<Mutex<History>>,
+    mentions: Arc<Mutex<Mentions>>,
+    recent: RecentIds,
+    typing: TypingThrottle,
+    limits: Limits,
+}
+
+impl Dispatcher {
+    /// # `handle_request`
+    /// Handles one request line from any client
+    fn handle_request(&mut self, line: &str) {
         // Acquire client list
-        let mut clients = match clients.try_lock() {
+        let mut clients = match metered_lock(&self.clients) {
             Ok(val) => val,
             Err(e) => {
                 server_log(
-                    format!("Error \"{}\" while acquiring client list for request \"{}\"", e, request),
+                    format!("Error \"{}\" while acquiring client list for request \"{}\"", e, line),
                     LogMessagType::EncounteredError,
                 );
                 return;
             }
         };
 
+        // Requests that want to be acknowledged start with their id
+        let (id, request) = split_request_id(line);
+
         // First 3 characters of a request
-        let identifier = &request[..3];
+        let identifier = request.get(..3).unwrap_or_default();
+        METRICS.count_message(match identifier {
+            rt::MESSAGE | rt::COMMAND | rt::TYPING => identifier,
+            _ => "invalid",
+        });
+
+        let sender = match request.split_ascii_whitespace().nth(1) {
+            Some(sender) => sender,
+            None => {
+                server_log(
+                    format!("Client sent request without a sender \"{}\"", request),
+                    LogMessagType::Warning,
+                );
+                return;
+            }
+        };
+
+        // A retry of a request that was already handled, its acknowledgement got lost
+        if let Some(id) = id.filter(|id| self.recent.contains(sender, id)) {
+            server_log(format!("Request {} from {} was already handled", id, sender), LogMessagType::Information);
+            let _ = clients.send_to(&sender.to_string(), &format!("{} {}", super::resposne_type::ACKNOWLEDGED, id));
+            return;
+        }
+
+        // Connections reject longer lines while reading them, bots, gateways and WebSocket frames with several lines are checked here
+        if let Some(max_message_length) = self.limits.max_message_length.filter(|max| line.len() > *max) {
+            Server::reject(&mut clients, sender, id, &format!("Message is too long (at most {} bytes)", max_message_length));
+            return;
+        }
 
-        let sender = request.split_ascii_whitespace().nth(1).unwrap();
+        // Messages and commands count as activity, typing does not
+        if identifier != rt::TYPING {
+            clients.touch(&sender.to_string());
+        }
 
         // Where the rest of the request starts
         let request_start = request.find(sender).unwrap() + sender.len() + 1;
-        let message_content = request[request_start..].to_string();
+        let mut message_content = request.get(request_start..).unwrap_or_default().to_string();
+        let mut receipts = Vec::new();
+        let mut renamed = None;
 
         match identifier {
             rt::MESSAGE => {
-                // Public message
-                clients.send_to_all(request);
+                // Public message, hooks may change or reject it first
+                if let Some(reason) = self.hooks.iter().find_map(|hook| hook.on_message(sender, &mut message_content).err()) {
+                    Server::reject(&mut clients, sender, id, &reason);
+                    return;
+                }
+                let stamp = Stamp::next();
+                clients.send_to_all(&format!("{} {} {} {}", rt::MESSAGE, stamp, sender, message_content));
+                // No author for HTTP API posts, no client can go by the name of an API token
+                if let Ok(mut history) = self.history.lock() {
+                    history.push(stamp, None, sender, clients.id_of(&sender.to_string()), &message_content);
+                }
+                if let Ok(mut mentions) = self.mentions.lock() {
+                    mentions.notify(&mut clients, stamp, sender, &message_content);
+                }
+            }
+            rt::COMMAND if message_content.trim() == super::commands::LOGOUT => {
+                // Logging out (also sent for lost connections) can not be stopped by hooks or left out of the registry
+                let result = disconnect(&mut clients, &self.hooks, sender);
+                self.typing.forget_offline(&clients);
+                if let Err(reason) = result {
+                    Server::reject(&mut clients, sender, id, &reason);
+                    return;
+                }
             }
             rt::COMMAND => {
-                // Special commands
-                let command = message_content.split_ascii_whitespace().nth(0).unwrap();
-
-                match command {
-                    super::commands::WHISPER => {
-                        // Direct messages
-                        // Acquire target
-                        let message_tokens : Vec<&str> = message_content.split_ascii_whitespace().collect();
-
-                        if message_tokens.len() < 3 { // Errorenous whisper
-                            return;
-                        }
-
-                        let target = message_tokens
-                            .get(1)
-                            .unwrap()
-                            .to_string();
-                        if let Ok(_) = clients.send_to(
-                            &target.clone(),
-                            &format!(
-                                "{} {} {}",
-                                super::resposne_type::PRIVATE_MESSAGE,
-                                sender,
-                                message_content
-                                    [message_content.find(&target).unwrap() + target.len() + 1..]
-                                    .to_string()
-                            ),
-                        ) {}
-                    }
-                    super::commands::LOGOUT => {
-                        // Remove client from server
-                        if let Err(e) = clients.remove(sender.to_string()) {
-                            server_log(format!("Error while removing client {} \"{}\"", sender, e), LogMessagType::EncounteredError);
-                            return;
-                        }
-
-                        // Tell other clients
-                        clients.send_to_all(&format!(
-                            "{} {}",
-                            super::resposne_type::USER_LEFT,
-                            sender
-                        ));
+                // Special commands, hooks may change or reject them first
+                if let Some(reason) = self.hooks.iter().find_map(|hook| hook.on_command(sender, &mut message_content).err()) {
+                    Server::reject(&mut clients, sender, id, &reason);
+                    return;
+                }
+                let mut context = CommandContext {
+                    sender,
+                    clients: &mut clients,
+                    bans: &self.bans,
+                    config: &self.config,
+                    registry: &self.commands,
+                    hooks: &self.hooks,
+                    history: &self.history,
+                    mentions: &self.mentions,
+                    receipts: Vec::new(),
+                    renamed: None,
+                };
+                let result = CommandRegistry::run(&mut context, &message_content);
+                receipts = context.receipts;
+                renamed = context.renamed;
+                // Logging out and renaming leave typing indicators behind
+                self.typing.forget_offline(&clients);
+                if let Err(reason) = result {
+                    Server::reject(&mut clients, sender, id, &reason);
+                    return;
+                }
+            }
+            rt::TYPING => {
+                // Relayed at most every TYPING_THROTTLE, a stop only if the start before it was relayed
+                let mut parts = message_content.split_ascii_whitespace();
+                let (state, target) = (parts.next().unwrap_or_default(), parts.next().map(|target| target.to_string()));
+                // Nobody to tell. Indicators without an id are dropped quietly, clients send them while whispers are being written
+                if let Some(target) = target.as_ref().filter(|target| clients.name_exists(target).is_none()) {
+                    match id {
+                        Some(_) => Server::reject(&mut clients, sender, id, &format!("{} is not online", target)),
+                        None => server_log(format!("Dropped typing indicator of {} to {} who is not online", sender, target), LogMessagType::Information),
                     }
+                    return;
+                }
+                let relay = match state {
+                    rt::TYPING_START => self.typing.start(sender, target.as_deref()),
+                    rt::TYPING_STOP => self.typing.stop(sender, target.as_deref()),
                     _ => {
-                        server_log(
-                            format!("Client sent invalid command \"{}\"", command),
-                            LogMessagType::Information,
-                        );
+                        Server::reject(&mut clients, sender, id, "Typing indicators are start or stop");
                         return;
                     }
+                };
+                if relay {
+                    let indicator = format!("{} {} {}", super::resposne_type::TYPING, sender, state);
+                    match target {
+                        Some(target) => {
+                            let _ = clients.send_to(&target, &indicator);
+                        }
+                        None => {
+                            for name in clients.names().into_iter().filter(|name| name != sender) {
+                                let _ = clients.send_to(&name, &indicator);
+                            }
+                        }
+                    }
                 }
             }
 
             _ => {
                 server_log(
                     format!("Client sent invalid identifier \"{}\"", identifier),
-                    LogMessagType::Information,
+                    LogMessagType::Warning,
                 );
                 return;
             }
         }
 
+        // Acknowledge, then hand out the delivery receipts. A client that was just renamed gets them under its new name
+        let sender = renamed.as_deref().unwrap_or(sender);
+        if let Some(id) = id {
+            self.recent.remember(sender, id);
+            let _ = clients.send_to(&sender.to_string(), &format!("{} {}", super::resposne_type::ACKNOWLEDGED, id));
+            for recipient in receipts {
+                let _ = clients.send_to(&sender.to_string(), &format!("{} {} {}", super::resposne_type::DELIVERED, id, recipient));
+            }
+        }
+
         server_log(
-            format!("Request \"{}\" handled", request),
+            format!("Request \"{}\" handled", line),
             LogMessagType::RequestHandled,
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clients(names: &[&str]) -> ClientList {
+        let mut clients = ClientList::new();
+        for name in names {
+            clients.add_bot(name.to_string(), mpsc::channel().0).unwrap();
+        }
+        clients
+    }
+
+    #[test]
+    fn typing_start_is_relayed_once_per_throttle() {
+        let mut typing = TypingThrottle::new(Duration::from_secs(60));
+        assert!(typing.start("alice", None));
+        assert!(!typing.start("alice", None));
+        // Every recipient has its own throttle
+        assert!(typing.start("alice", Some("bob")));
+        assert!(typing.start("bob", None));
+
+        let mut typing = TypingThrottle::new(Duration::ZERO);
+        assert!(typing.start("alice", None));
+        assert!(typing.start("alice", None));
+    }
+
+    #[test]
+    fn typing_stop_is_relayed_only_after_a_start() {
+        let mut typing = TypingThrottle::new(TYPING_THROTTLE);
+        assert!(!typing.stop("alice", None));
+        typing.start("alice", None);
+        assert!(typing.stop("alice", None));
+        assert!(!typing.stop("alice", None));
+    }
+
+    #[test]
+    fn typing_forgets_clients_that_are_gone() {
+        let mut typing = TypingThrottle::new(TYPING_THROTTLE);
+        typing.start("alice", None);
+        typing.start("alice", Some("bob"));
+        typing.start("bob", None);
+        typing.start("carol", Some("alice"));
+
+        typing.forget_offline(&clients(&["alice", "carol"]));
+        let mut left: Vec<_> = typing.relayed.keys().cloned().collect();
+        left.sort();
+        assert_eq!(left, vec![("alice".to_string(), None), ("carol".to_string(), Some("alice".to_string()))]);
+    }
+
+    #[test]
+    fn recent_ids_recognize_retries() {
+        let mut recent = RecentIds::default();
+        recent.remember("alice", "1");
+        assert!(recent.contains("alice", "1"));
+        assert!(!recent.contains("bob", "1"));
+        assert!(!recent.contains("alice", "2"));
+    }
+
+    #[test]
+    fn recent_ids_forget_the_oldest_of_anyone() {
+        let mut recent = RecentIds::default();
+        recent.remember("alice", "first");
+        recent.remember("alice", "first");
+        for i in 1..RECENT_IDS {
+            recent.remember(if i % 2 == 0 { "alice" } else { "bob" }, &i.to_string());
+        }
+        assert!(recent.contains("alice", "first"));
+
+        recent.remember("carol", "1");
+        assert!(!recent.contains("alice", "first"));
+        assert!(recent.contains("bob", "1"));
+        assert_eq!(recent.ids.len(), RECENT_IDS);
+        assert_eq!(recent.order.len(), RECENT_IDS);
+    }
+
+    #[test]
+    fn split_request_id_only_takes_a_prefixed_word() {
+        assert_eq!(split_request_id("@7 MSG azeez hi"), (Some("7"), "MSG azeez hi"));
+        assert_eq!(split_request_id("MSG azeez @7 hi"), (None, "MSG azeez @7 hi"));
+        assert_eq!(split_request_id("@7"), (None, "@7"));
+    }
+
+    #[test]
+    fn read_request_line_replaces_invalid_utf8() {
+        let mut reader: &[u8] = b"MSG azeez caf\xe9\r\nMSG azeez ok\n";
+        assert_eq!(read_request_line(&mut reader, 100).unwrap(), RequestLine::Line("MSG azeez caf\u{fffd}".to_string()));
+        assert_eq!(read_request_line(&mut reader, 100).unwrap(), RequestLine::Line("MSG azeez ok".to_string()));
+        assert_eq!(read_request_line(&mut reader, 100).unwrap(), RequestLine::Closed);
+    }
+
+    #[test]
+    fn read_request_line_skips_the_rest_of_long_lines() {
+        let mut input = b"MSG a 12345\r\n@1 MSG a ".to_vec();
+        input.extend(vec![b'x'; 100_000]);
+        input.extend(b"\nMSG a ok\n");
+        let mut reader = io::BufReader::with_capacity(64, input.as_slice());
+
+        // Exactly as long as allowed, line ending not counted
+        assert_eq!(read_request_line(&mut reader, 11).unwrap(), RequestLine::Line("MSG a 12345".to_string()));
+        match read_request_line(&mut reader, 11).unwrap() {
+            RequestLine::TooLong(start) => assert_eq!(split_request_id(&start).0, Some("1")),
+            _ => panic!("Long line was read"),
+        }
+        assert_eq!(read_request_line(&mut reader, 11).unwrap(), RequestLine::Line("MSG a ok".to_string()));
+    }
+}
@@ -0,0 +1,207 @@
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::Mutex,
+};
+
+use super::{server_log, LogMessagType};
+
+/// Where the server writes its audit trail unless told otherwise
+pub const DEFAULT_AUDIT_FILE: &str = "audit.log";
+
+/// The open audit file. `None` until `open_audit_log` is called, audit events are dropped until then
+static AUDIT_FILE: Mutex<Option<File>> = Mutex::new(None);
+
+/// # `AuditEvent`
+/// Moderation and security relevant things that happened on the server
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEvent {
+    Join,
+    Leave,
+    Denied,
+    NameCollision,
+    Kick,
+    Ban,
+    Unban,
+    AuthFailure,
+    Rename,
+}
+
+/// # `AuditRecord`
+/// One line of the audit trail
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuditRecord {
+    pub time: DateTime<Utc>,
+    pub event: AuditEvent,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl AuditRecord {
+    /// # `ip`
+    /// The IP part of the record's address, if it has one
+    pub fn ip(&self) -> Option<IpAddr> {
+        let address = self.address.as_ref()?;
+        address
+            .parse::<SocketAddr>()
+            .map(|address| address.ip())
+            .or_else(|_| address.parse::<IpAddr>())
+            .ok()
+    }
+}
+
+/// # `open_audit_log`
+/// Opens (or creates) the audit file at `path` in append-only mode. Every `audit` call after this is written to it
+pub fn open_audit_log(path: &Path) -> io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    match AUDIT_FILE.lock() {
+        Ok(mut audit_file) => *audit_file = Some(file),
+        Err(e) => return Err(io::Error::other(e.to_string())),
+    }
+    Ok(())
+}
+
+/// # `audit`
+/// Appends an event to the audit trail. `user`, `address` and `detail` are left out of the record when `None`
+pub fn audit(event: AuditEvent, user: Option<&str>, address: Option<String>, detail: Option<String>) {
+    let record = AuditRecord {
+        time: Utc::now(),
+        event,
+        user: user.map(|user| user.to_string()),
+        address,
+        detail,
+    };
+
+    let line = match serde_json::to_string(&record) {
+        Ok(line) => line,
+        Err(e) => {
+            server_log(format!("Error \"{}\" serializing audit record", e), LogMessagType::EncounteredError);
+            return;
+        }
+    };
+
+    if let Ok(mut file) = AUDIT_FILE.lock() {
+        if let Some(file) = file.as_mut() {
+            if let Err(e) = writeln!(file, "{}", line) {
+                server_log(format!("Error \"{}\" writing to audit log", e), LogMessagType::EncounteredError);
+            }
+        }
+    }
+}
+
+/// # `AuditQuery`
+/// Filter used to search the audit trail. Every field that is `Some` has to match
+#[derive(Default)]
+pub struct AuditQuery {
+    pub user: Option<String>,
+    pub ip: Option<IpAddr>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl AuditQuery {
+    /// # `matches`
+    /// Whether the record passes the filter
+    pub fn matches(&self, record: &AuditRecord) -> bool {
+        self.user.as_ref().is_none_or(|user| record.user.as_ref() == Some(user))
+            && self.ip.is_none_or(|ip| record.ip() == Some(ip))
+            && self.since.is_none_or(|since| record.time >= since)
+            && self.until.is_none_or(|until| record.time <= until)
+    }
+
+    /// # `run`
+    /// Reads the audit file at `path` and returns the records that match. Lines that are not valid records are skipped
+    pub fn run(&self, path: &Path) -> io::Result<Vec<AuditRecord>> {
+        let mut records = Vec::new();
+        for line in BufReader::new(File::open(path)?).lines() {
+            if let Ok(record) = serde_json::from_str::<AuditRecord>(&line?) {
+                if self.matches(&record) {
+                    records.push(record);
+                }
+            }
+        }
+        Ok(records)
+    }
+}
+
+/// # `parse_time`
+/// Parses a point in time given on the command line. Accepts RFC 3339 (`2021-12-01T10:00:00Z`),
+/// local `YYYY-MM-DD HH:MM:SS` and local `YYYY-MM-DD` (midnight)
+pub fn parse_time(time: &str) -> Option<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(time) {
+        return Some(time.with_timezone(&Utc));
+    }
+
+    let naive = NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S")
+        .ok()
+        .or_else(|| NaiveDate::parse_from_str(time, "%Y-%m-%d").ok()?.and_hms_opt(0, 0, 0))?;
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(time: &str, user: &str, address: &str) -> AuditRecord {
+        AuditRecord {
+            time: parse_time(time).unwrap(),
+            event: AuditEvent::Join,
+            user: Some(user.to_string()),
+            address: Some(address.to_string()),
+            detail: None,
+        }
+    }
+
+    #[test]
+    fn parse_time_accepts_rfc3339_and_local_times() {
+        assert_eq!(
+            parse_time("2021-12-01T10:00:00+01:00"),
+            Some(Utc.with_ymd_and_hms(2021, 12, 1, 9, 0, 0).unwrap())
+        );
+        let local = Local.with_ymd_and_hms(2021, 12, 1, 10, 30, 0).unwrap().with_timezone(&Utc);
+        assert_eq!(parse_time("2021-12-01 10:30:00"), Some(local));
+        let midnight = Local.with_ymd_and_hms(2021, 12, 1, 0, 0, 0).unwrap().with_timezone(&Utc);
+        assert_eq!(parse_time("2021-12-01"), Some(midnight));
+        assert_eq!(parse_time("yesterday"), None);
+        assert_eq!(parse_time("2021-13-01"), None);
+    }
+
+    #[test]
+    fn matches_includes_both_ends_of_the_time_range() {
+        let query = AuditQuery {
+            since: parse_time("2021-12-01T10:00:00Z"),
+            until: parse_time("2021-12-01T11:00:00Z"),
+            ..AuditQuery::default()
+        };
+        assert!(query.matches(&record("2021-12-01T10:00:00Z", "azeez", "127.0.0.1:5000")));
+        assert!(query.matches(&record("2021-12-01T11:00:00Z", "azeez", "127.0.0.1:5000")));
+        assert!(!query.matches(&record("2021-12-01T09:59:59Z", "azeez", "127.0.0.1:5000")));
+        assert!(!query.matches(&record("2021-12-01T11:00:01Z", "azeez", "127.0.0.1:5000")));
+    }
+
+    #[test]
+    fn matches_user_and_ip_with_or_without_port() {
+        let query = AuditQuery {
+            user: Some("azeez".to_string()),
+            ip: "10.0.0.5".parse().ok(),
+            ..AuditQuery::default()
+        };
+        assert!(query.matches(&record("2021-12-01T10:00:00Z", "azeez", "10.0.0.5:5000")));
+        assert!(query.matches(&record("2021-12-01T10:00:00Z", "azeez", "10.0.0.5")));
+        assert!(!query.matches(&record("2021-12-01T10:00:00Z", "bob", "10.0.0.5:5000")));
+        assert!(!query.matches(&record("2021-12-01T10:00:00Z", "azeez", "10.0.0.6:5000")));
+        assert!(AuditQuery::default().matches(&record("2021-12-01T10:00:00Z", "bob", "/tmp/chat.sock")));
+    }
+}
@@ -0,0 +1,51 @@
+use std::{collections::HashSet, net::IpAddr};
+
+/// # `BanList`
+/// Names and addresses that are not allowed on the server
+pub struct BanList {
+    names: HashSet<String>,
+    addresses: HashSet<IpAddr>,
+}
+
+impl BanList {
+    /// # `new`
+    /// Creates an empty `BanList`
+    pub fn new() -> BanList {
+        BanList {
+            names: HashSet::new(),
+            addresses: HashSet::new(),
+        }
+    }
+
+    /// # `ban_name`
+    /// Bans a name
+    pub fn ban_name(&mut self, name: String) {
+        self.names.insert(name);
+    }
+
+    /// # `ban_address`
+    /// Bans an address
+    pub fn ban_address(&mut self, address: IpAddr) {
+        self.addresses.insert(address);
+    }
+
+    /// # `unban`
+    /// Takes a name or an address as `&str` and lifts its ban. Returns `Err` if it was not banned
+    pub fn unban(&mut self, target: &str) -> Result<(), &str> {
+        let removed = match target.parse::<IpAddr>() {
+            Ok(address) => self.addresses.remove(&address),
+            Err(_) => self.names.remove(target),
+        };
+        if removed {
+            Ok(())
+        } else {
+            Err("Not banned")
+        }
+    }
+
+    /// # `is_banned`
+    /// Whether the name or the address is banned
+    pub fn is_banned(&self, name: &str, address: Option<IpAddr>) -> bool {
+        self.names.contains(name) || address.is_some_and(|address| self.addresses.contains(&address))
+    }
+}
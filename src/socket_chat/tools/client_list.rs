@@ -1,9 +1,173 @@
-use std::{io::Write, net::TcpStream};
+use chrono::{DateTime, Local};
+use std::{
+    fmt,
+    io::{self, Write},
+    net::{IpAddr, Shutdown, SocketAddr},
+    sync::{mpsc::Sender, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use super::{server_log, LogMessagType, METRICS};
+use crate::socket_chat::resposne_type;
+use crate::socket_chat::irc::IrcConnection;
+use crate::socket_chat::transport::Stream;
+use crate::socket_chat::websocket::{opcode, write_frame};
+
+/// # `Connection`
+/// Where the responses for a client go
+pub(crate) enum Connection {
+    /// A client connected over TCP or a Unix socket
+    Socket(Stream),
+    /// A bot hosted by the server, it gets the response lines over a channel
+    Bot(Sender<String>),
+    /// A browser client, every response line is sent as a text frame
+    WebSocket(Stream),
+    /// An IRC client, response lines are translated to IRC
+    Irc(IrcConnection),
+}
+
+impl Connection {
+    /// # `send_line`
+    /// Sends one response line, newline terminated for sockets
+    pub(crate) fn send_line(&mut self, line: &str) -> io::Result<()> {
+        match self {
+            Connection::Socket(socket) => writeln!(socket, "{}", line),
+            Connection::Bot(sender) => sender
+                .send(line.to_string())
+                .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Bot has stopped")),
+            Connection::WebSocket(socket) => write_frame(socket, opcode::TEXT, line.as_bytes()),
+            Connection::Irc(connection) => connection.send_line(line),
+        }
+    }
+
+    /// # `close`
+    /// Closes the socket. A bot stops once its channel is dropped
+    fn close(&mut self) {
+        match self {
+            Connection::Socket(socket) => {
+                let _ = socket.shutdown(Shutdown::Both);
+            }
+            Connection::WebSocket(socket) => {
+                let _ = write_frame(socket, opcode::CLOSE, &[]);
+                let _ = socket.shutdown(Shutdown::Both);
+            }
+            Connection::Irc(connection) => connection.close(),
+            Connection::Bot(_) => {}
+        }
+    }
+}
+
+/// # `Presence`
+/// Whether a client is around. Away and busy clients may leave a status message
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Presence {
+    #[default]
+    Online,
+    Away(Option<String>),
+    Busy(Option<String>),
+}
+
+impl Presence {
+    /// # `parse`
+    /// Takes the state (`online`, `away` or `busy`) and the status message. `None` if the state is unknown
+    pub fn parse(state: &str, message: Option<&str>) -> Option<Presence> {
+        let message = message.map(|message| message.to_string());
+        match state {
+            "online" => Some(Presence::Online),
+            "away" => Some(Presence::Away(message)),
+            "busy" => Some(Presence::Busy(message)),
+            _ => None,
+        }
+    }
+
+    /// # `state`
+    /// `online`, `away` or `busy`
+    pub fn state(&self) -> &'static str {
+        match self {
+            Presence::Online => "online",
+            Presence::Away(_) => "away",
+            Presence::Busy(_) => "busy",
+        }
+    }
+
+    /// # `message`
+    /// The status message of an away or busy client
+    pub fn message(&self) -> Option<&str> {
+        match self {
+            Presence::Online => None,
+            Presence::Away(message) | Presence::Busy(message) => message.as_deref(),
+        }
+    }
+}
+
+impl fmt::Display for Presence {
+    /// The state with the status message, if there is one (`away: lunch`)
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.message() {
+            Some(message) => write!(f, "{}: {}", self.state(), message),
+            None => write!(f, "{}", self.state()),
+        }
+    }
+}
+
+/// # `ClientName`
+/// The current name of a connected client, shared with the thread reading its requests so that thread follows renames
+#[derive(Clone, Debug)]
+pub(crate) struct ClientName(Arc<Mutex<String>>);
+
+impl ClientName {
+    fn new(name: &str) -> ClientName {
+        ClientName(Arc::new(Mutex::new(name.to_string())))
+    }
+
+    /// # `get`
+    pub(crate) fn get(&self) -> String {
+        self.0.lock().map(|name| name.clone()).unwrap_or_default()
+    }
+
+    fn set(&self, name: &str) {
+        if let Ok(mut current) = self.0.lock() {
+            *current = name.to_string();
+        }
+    }
+}
+
+/// # `ClientEntry`
+/// A connected client: its name, its connection, where it connected from and when
+struct ClientEntry {
+    /// Given when the client joins and never reused, it stays the same when the name changes
+    id: u64,
+    name: String,
+    /// The same name, as seen by the client's reading thread
+    shared_name: ClientName,
+    connection: Connection,
+    address: Option<SocketAddr>,
+    connected_at: DateTime<Local>,
+    presence: Presence,
+    /// When the client last sent a message or command
+    last_active: Instant,
+    /// Whether the client joined with the operator password
+    operator: bool,
+}
+
+/// # `ClientInfo`
+/// Public information about a connected client, as returned by `ClientList::list`
+pub struct ClientInfo {
+    pub name: String,
+    pub address: Option<SocketAddr>,
+    pub connected_at: DateTime<Local>,
+    pub presence: Presence,
+    /// How long ago the client last sent a message or command
+    pub idle: Duration,
+}
 
 /// # `ClientList`
 /// Structure that takes care of a list of Client sockets and their names.
+#[derive(Default)]
 pub struct ClientList {
-    clients: Vec<(String, TcpStream)>,
+    clients: Vec<ClientEntry>,
+    /// Id of the next client that joins
+    next_id: u64,
 }
 
 impl ClientList {
@@ -12,27 +176,87 @@ impl ClientList {
     pub fn new() -> ClientList {
         ClientList {
             clients: Vec::new(),
+            next_id: 0,
         }
     }
 
     /// # `add`
-    /// Adds a socket and its user's name to the list. Returns a `Result<(), &str>` if name exists in client list already.
-    pub fn add(&mut self, name: String, socket: TcpStream) -> Result<(), &str> {
-        if let Some(_) = self.name_exists(&name) {
+    /// Adds a socket and its user's name to the list. Returns a `Result<(), &str>` if the name is invalid or exists in client list already.
+    pub fn add(&mut self, name: String, socket: Stream) -> Result<(), &str> {
+        let address = socket.peer_addr();
+        self.add_connection(name, Connection::Socket(socket), address).map(|_| ())
+    }
+
+    /// # `add_bot`
+    /// Adds a bot hosted by the server under `name`, its responses are sent to `responses`. Returns `Err` if the name is invalid or taken
+    pub fn add_bot(&mut self, name: String, responses: Sender<String>) -> Result<(), &str> {
+        self.add_connection(name, Connection::Bot(responses), None).map(|_| ())
+    }
+
+    /// # `add_connection`
+    /// Adds a client of any kind of connection and returns its name as the thread reading its requests sees it.
+    /// Returns `Err` if the name is invalid or taken
+    pub(crate) fn add_connection(&mut self, name: String, connection: Connection, address: Option<SocketAddr>) -> Result<ClientName, &str> {
+        self.check_name(&name)?;
+        let shared_name = ClientName::new(&name);
+        self.next_id += 1;
+        self.clients.push(ClientEntry {
+            id: self.next_id,
+            name,
+            shared_name: shared_name.clone(),
+            connection,
+            address,
+            connected_at: Local::now(),
+            presence: Presence::Online,
+            last_active: Instant::now(),
+            operator: false,
+        });
+        METRICS.set_connected_clients(self.clients.len());
+        Ok(shared_name)
+    }
+
+    /// # `check_name`
+    /// Whether a client may take `name`: it has to be a valid name (see `validate_name`) that nobody else has
+    pub fn check_name(&self, name: &str) -> Result<(), &'static str> {
+        Self::validate_name(name)?;
+        if self.name_exists(&name.to_string()).is_some() {
             Err("Name already exists!")
         } else {
-            self.clients.push((name, socket));
             Ok(())
         }
     }
 
+    /// # `validate_name`
+    /// Whether `name` can be a name at all, taken or not: it has to be a single word
+    pub fn validate_name(name: &str) -> Result<(), &'static str> {
+        if name.is_empty() {
+            Err("Name can not be empty")
+        } else if name.contains(char::is_whitespace) {
+            Err("Name can not contain spaces")
+        } else {
+            Ok(())
+        }
+    }
+
+    /// # `rename`
+    /// Gives the client `name` the name `new_name`, checked like a name in `add`. Its connection, presence and idle time stay.
+    /// Returns `Err` if the client does not exist or the new name is invalid or taken
+    pub fn rename(&mut self, name: &String, new_name: &str) -> Result<(), &str> {
+        let idx = self.name_exists(name).ok_or("Client does not exists in list")?;
+        self.check_name(new_name)?;
+        let client = &mut self.clients[idx];
+        client.name = new_name.to_string();
+        client.shared_name.set(new_name);
+        Ok(())
+    }
+
     /// # `name_exists`
     /// Takes a name as a `&String` and returns `Option<usize>` containing the index in the vector of the list.
     /// Else if the user does not exists then it return `None`
     pub fn name_exists(&self, name: &String) -> Option<usize> {
         // I thought about using a HashMap for direct access but it became more complex especially when sending. Linear search should be fine, I hope.
         for (i, client) in self.clients.iter().enumerate() {
-            if client.0 == *name {
+            if client.name == *name {
                 return Some(i);
             }
         }
@@ -40,29 +264,148 @@ impl ClientList {
         None
     }
 
+    /// # `id_of`
+    /// Takes a name as `&String` and returns the id of that client's connection, if it exists. Unlike the name it never changes
+    /// and is never given to another client
+    pub fn id_of(&self, name: &String) -> Option<u64> {
+        self.name_exists(name).map(|idx| self.clients[idx].id)
+    }
+
+    /// # `set_operator`
+    /// Marks the client `name` as having joined with the operator password
+    pub(crate) fn set_operator(&mut self, name: &String) {
+        if let Some(idx) = self.name_exists(name) {
+            self.clients[idx].operator = true;
+        }
+    }
+
+    /// # `joined_as_operator`
+    /// Whether the client `name` joined with the operator password. It stays so after renames
+    pub fn joined_as_operator(&self, name: &String) -> bool {
+        self.name_exists(name).is_some_and(|idx| self.clients[idx].operator)
+    }
+
+    /// # `address_of`
+    /// Takes a name as `&String` and returns the address that client connected from, if the client exists and the address is known
+    pub fn address_of(&self, name: &String) -> Option<SocketAddr> {
+        self.name_exists(name)
+            .and_then(|idx| self.clients[idx].address)
+    }
+
+    /// # `names_from`
+    /// Returns the names of every client connected from the given IP address
+    pub fn names_from(&self, ip: IpAddr) -> Vec<String> {
+        self.clients
+            .iter()
+            .filter(|client| client.address.is_some_and(|address| address.ip() == ip))
+            .map(|client| client.name.clone())
+            .collect()
+    }
+
+    /// # `names`
+    /// Returns the names of every client in the list
+    pub fn names(&self) -> Vec<String> {
+        self.clients.iter().map(|client| client.name.clone()).collect()
+    }
+
+    /// # `list`
+    /// Returns the name, address and connection time of every client
+    pub fn list(&self) -> Vec<ClientInfo> {
+        self.clients
+            .iter()
+            .map(|client| ClientInfo {
+                name: client.name.clone(),
+                address: client.address,
+                connected_at: client.connected_at,
+                presence: client.presence.clone(),
+                idle: client.last_active.elapsed(),
+            })
+            .collect()
+    }
+
+    /// # `presence_of`
+    /// Takes a name as `&String` and returns the presence of that client, if it exists
+    pub fn presence_of(&self, name: &String) -> Option<&Presence> {
+        self.name_exists(name).map(|idx| &self.clients[idx].presence)
+    }
+
+    /// # `set_presence`
+    /// Changes the presence of a client. Returns `Err` if the user does not exist
+    pub fn set_presence(&mut self, name: &String, presence: Presence) -> Result<(), &str> {
+        match self.name_exists(name) {
+            Some(idx) => {
+                self.clients[idx].presence = presence;
+                Ok(())
+            }
+            None => Err("Client does not exists in list"),
+        }
+    }
+
+    /// # `touch`
+    /// Marks a client as active now, its idle time starts over
+    pub fn touch(&mut self, name: &String) {
+        if let Some(idx) = self.name_exists(name) {
+            self.clients[idx].last_active = Instant::now();
+        }
+    }
+
+    /// # `idle_time`
+    /// How long ago a client last sent a message or command, if it exists
+    pub fn idle_time(&self, name: &String) -> Option<Duration> {
+        self.name_exists(name).map(|idx| self.clients[idx].last_active.elapsed())
+    }
+
+    /// # `presence_response`
+    /// The `PRS <name> <state> <idle seconds> [message]` line telling others about a client's presence
+    pub(crate) fn presence_response(&self, name: &String) -> Option<String> {
+        let idx = self.name_exists(name)?;
+        let client = &self.clients[idx];
+        let mut response = format!(
+            "{} {} {} {}",
+            resposne_type::PRESENCE,
+            client.name,
+            client.presence.state(),
+            client.last_active.elapsed().as_secs()
+        );
+        if let Some(message) = client.presence.message() {
+            response.push(' ');
+            response.push_str(message);
+        }
+        Some(response)
+    }
+
     /// # `send_to_all`
-    /// Send a given message as `String` to all clients in the list
-    pub fn send_to_all(&mut self, message: &String) {
-        let message = message.as_bytes();
+    /// Send a given message as `String` to all clients in the list. The message is newline terminated on the way out
+    pub fn send_to_all(&mut self, message: &str) {
+        let start = Instant::now();
         for client in self.clients.iter_mut() {
-            client
-                .1
-                .write(message)
-                .expect(format!("Error sending to client {}", client.0).as_str());
+            if let Err(e) = client.connection.send_line(message) {
+                METRICS.count_dropped_write();
+                server_log(
+                    format!("Error \"{}\" sending to client {}", e, client.name),
+                    LogMessagType::EncounteredError,
+                );
+            }
         }
+        METRICS.observe_broadcast(start.elapsed());
     }
 
     /// # `send_to`
-    /// Takes a target client name as `String` and a message as `String` and sends a message to that client.
-    /// This returns `Result<(), &str>` where Error is if the user does not exists
-    pub fn send_to(&mut self, target: &String, message: &String) -> Result<(), &str> {
-        if let Some(id) = self.name_exists(&target) {
-            self.clients
+    /// Takes a target client name as `String` and a message as `String` and sends a message to that client. The message is newline terminated on the way out.
+    /// This returns `Result<(), &str>` where Error is if the user does not exists or the message could not be written
+    pub fn send_to(&mut self, target: &String, message: &str) -> Result<(), &str> {
+        if let Some(id) = self.name_exists(target) {
+            let client = self.clients
                 .get_mut(id)
-                .expect("Error getting target client")
-                .1
-                .write(message.as_bytes())
-                .expect("Error sending message to target");
+                .expect("Error getting target client");
+            if let Err(e) = client.connection.send_line(message) {
+                METRICS.count_dropped_write();
+                server_log(
+                    format!("Error \"{}\" sending message to {}", e, client.name),
+                    LogMessagType::EncounteredError,
+                );
+                return Err("Could not write to client");
+            }
             Ok(())
         } else {
             Err("No such client")
@@ -74,7 +417,27 @@ impl ClientList {
     /// This returns `Result<(), &str>` where Error is if the user does not exists&
     pub fn remove(&mut self, name: String) -> Result<(), &str> {
         match self.name_exists(&name) {
-            Some(idx) => {self.clients.remove(idx); Ok(())},
+            Some(idx) => {
+                self.clients.remove(idx);
+                METRICS.set_connected_clients(self.clients.len());
+                Ok(())
+            },
+            _ => Err("Client does not exists in list")
+        }
+    }
+
+    /// # `kick`
+    /// Takes a name as `&String`, tells that client it has been disconnected, closes its socket and removes it from the list.
+    /// This returns `Result<(), &str>` where Error is if the user does not exists
+    pub fn kick(&mut self, name: &String) -> Result<(), &str> {
+        match self.name_exists(name) {
+            Some(idx) => {
+                let mut client = self.clients.remove(idx);
+                METRICS.set_connected_clients(self.clients.len());
+                let _ = client.connection.send_line(resposne_type::CONNECTION_DENIED);
+                client.connection.close();
+                Ok(())
+            }
             _ => Err("Client does not exists in list")
         }
     }
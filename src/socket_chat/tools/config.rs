@@ -0,0 +1,164 @@
+use serde::Deserialize;
+use std::{fs, net::IpAddr, path::Path};
+
+use super::ClientList;
+
+/// # `Config`
+/// Server settings read from a TOML file. Every field is optional, a missing key keeps its default.
+/// The file is read on startup and again whenever a reload is requested through the admin socket
+#[derive(Deserialize, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Log threshold (`error`, `warn`, `info`, `debug` or `trace`)
+    pub log_level: Option<String>,
+    /// Names that are not allowed to join
+    pub banned_names: Vec<String>,
+    /// Addresses that are not allowed to connect
+    pub banned_addresses: Vec<IpAddr>,
+    /// Message of the day, sent to every client right after it joins. Every line becomes its own system message
+    pub motd: Option<String>,
+    /// Names that may use operator commands such as `/announce`. Joining under one of them needs `operator_password`
+    pub operators: Vec<String>,
+    /// What operators join with (`CON <name> <password>`). Without it nobody can join under an operator's name
+    pub operator_password: Option<String>,
+    /// System messages broadcast on a timer
+    pub announcements: Vec<Announcement>,
+    /// Tokens that may use the HTTP API
+    pub api_tokens: Vec<ApiToken>,
+}
+
+/// # `Announcement`
+/// A system message broadcast every `every_secs` seconds
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Announcement {
+    pub message: String,
+    pub every_secs: u64,
+}
+
+/// # `ApiToken`
+/// A secret that lets HTTP API callers post as `name`, which has to be a valid client name. Clients can not take the name
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ApiToken {
+    pub name: String,
+    pub token: String,
+}
+
+impl Config {
+    /// # `load`
+    /// Reads and parses the config file at `path`. Returns `Err` with a readable reason if it could not be read or is invalid
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Error \"{}\" reading config file {}", e, path.display()))?;
+        let config: Config = toml::from_str(&contents)
+            .map_err(|e| format!("Invalid config file {}: {}", path.display(), e.message()))?;
+        config
+            .validate()
+            .map_err(|e| format!("Invalid config file {}: {}", path.display(), e))?;
+        Ok(config)
+    }
+
+    /// # `validate`
+    /// Checks what the TOML types can not: every API token posts under a valid client name
+    pub fn validate(&self) -> Result<(), String> {
+        for api_token in self.api_tokens.iter() {
+            ClientList::validate_name(&api_token.name)
+                .map_err(|e| format!("API token name \"{}\": {}", api_token.name, e))?;
+            if self.operators.contains(&api_token.name) {
+                return Err(format!("API token name \"{}\" is an operator's", api_token.name));
+            }
+        }
+        Ok(())
+    }
+
+    /// # `authenticate`
+    /// Checks the password a client joins `name` with. Returns whether it joins as an operator, or `Err` if `name` is an operator's
+    /// and the password is missing or wrong. Other names join whatever password they give
+    pub fn authenticate(&self, name: &str, password: Option<&str>) -> Result<bool, &'static str> {
+        if !self.operators.iter().any(|operator| operator == name) {
+            return Ok(false);
+        }
+        match (password, self.operator_password.as_deref()) {
+            (Some(password), Some(expected)) if constant_time_eq(password.as_bytes(), expected.as_bytes()) => Ok(true),
+            _ => Err("Operator names need the operator password"),
+        }
+    }
+
+    /// # `is_api_name`
+    /// Whether an API token posts as `name`. Clients can not take these names, so what the API posts is never taken for a client's
+    pub fn is_api_name(&self, name: &str) -> bool {
+        self.api_tokens.iter().any(|api_token| api_token.name == name)
+    }
+
+    /// # `api_user`
+    /// The name an API token posts as, `None` if the token is unknown. Every token is compared in full, so how long
+    /// this takes does not tell how much of a guess was right
+    pub fn api_user(&self, token: &str) -> Option<&str> {
+        self.api_tokens.iter().fold(None, |found, api_token| {
+            match constant_time_eq(api_token.token.as_bytes(), token.as_bytes()) {
+                true if found.is_none() => Some(api_token.name.as_str()),
+                _ => found,
+            }
+        })
+    }
+}
+
+/// # `constant_time_eq`
+/// Whether two secrets are equal, looking at every byte even after a difference. Only the lengths can be told apart by timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_tokens(tokens: &[(&str, &str)]) -> Config {
+        Config {
+            api_tokens: tokens
+                .iter()
+                .map(|(name, token)| ApiToken { name: name.to_string(), token: token.to_string() })
+                .collect(),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn api_user_matches_whole_tokens_only() {
+        let config = with_tokens(&[("ci", "secret"), ("bot", "other")]);
+        assert_eq!(config.api_user("secret"), Some("ci"));
+        assert_eq!(config.api_user("other"), Some("bot"));
+        assert_eq!(config.api_user("secre"), None);
+        assert_eq!(config.api_user("secrets"), None);
+        assert_eq!(config.api_user(""), None);
+    }
+
+    #[test]
+    fn authenticate_needs_the_password_for_operator_names_only() {
+        let config = Config {
+            operators: vec!["admin".to_string()],
+            operator_password: Some("secret".to_string()),
+            ..Config::default()
+        };
+        assert_eq!(config.authenticate("admin", Some("secret")), Ok(true));
+        assert!(config.authenticate("admin", Some("secre")).is_err());
+        assert!(config.authenticate("admin", None).is_err());
+        assert_eq!(config.authenticate("bob", None), Ok(false));
+        assert_eq!(config.authenticate("bob", Some("secret")), Ok(false));
+
+        // Without a password operator names can not be taken at all
+        let config = Config { operator_password: None, ..config };
+        assert!(config.authenticate("admin", None).is_err());
+        assert!(config.authenticate("admin", Some("")).is_err());
+    }
+
+    #[test]
+    fn validate_refuses_token_names_that_are_not_client_names() {
+        assert!(with_tokens(&[("ci", "secret")]).validate().is_ok());
+        assert!(with_tokens(&[("two words", "secret")]).validate().is_err());
+        assert!(with_tokens(&[("", "secret")]).validate().is_err());
+        let config = Config { operators: vec!["ci".to_string()], ..with_tokens(&[("ci", "secret")]) };
+        assert!(config.validate().is_err());
+    }
+}
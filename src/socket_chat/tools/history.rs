@@ -0,0 +1,271 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// How many public messages the server remembers
+pub const HISTORY_SIZE: usize = 500;
+/// Most different emoji one message can get
+pub const MAX_REACTIONS: usize = 20;
+
+/// Id of the next message the server stamps
+static NEXT_MESSAGE_ID: AtomicU64 = AtomicU64::new(1);
+
+/// # `Stamp`
+/// The id and time the server gives every public and private message. Ids only ever grow, they are shared by public and private messages
+#[derive(Clone, Copy, Debug)]
+pub struct Stamp {
+    pub id: u64,
+    pub time: DateTime<Utc>,
+}
+
+impl Stamp {
+    /// # `next`
+    /// Stamps a message with the next id and the current time
+    pub fn next() -> Stamp {
+        Stamp {
+            id: NEXT_MESSAGE_ID.fetch_add(1, Ordering::SeqCst),
+            time: Utc::now(),
+        }
+    }
+}
+
+impl fmt::Display for Stamp {
+    /// `<id> <time>`, the way it is sent to clients (`42 2021-12-01T10:00:00.000Z`)
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.id, self.time.to_rfc3339_opts(SecondsFormat::Millis, true))
+    }
+}
+
+/// # `HistoryEntry`
+/// A public message as it was broadcast
+#[derive(Serialize, Clone)]
+pub struct HistoryEntry {
+    pub id: u64,
+    pub time: DateTime<Utc>,
+    pub sender: String,
+    /// The connection that sent the message, it stays the same when the sender changes their name. `None` for messages
+    /// that did not come from a connected client, such as those posted through the HTTP API
+    #[serde(skip)]
+    pub author: Option<u64>,
+    pub message: String,
+    /// Id of the message this one replies to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<u64>,
+    /// When the message was last edited
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edited: Option<DateTime<Utc>>,
+    /// Who reacted with which emoji
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub reactions: BTreeMap<String, Vec<String>>,
+}
+
+/// # `History`
+/// The latest public messages, oldest first. Once full the oldest one is dropped for every new one
+pub struct History {
+    entries: VecDeque<HistoryEntry>,
+    capacity: usize,
+}
+
+impl History {
+    /// # `new`
+    /// Creates an empty history keeping at most `capacity` messages
+    pub fn new(capacity: usize) -> History {
+        History {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// # `push`
+    /// Remembers a message under the stamp it was broadcast with. `reply_to` is the id of the message it replies to,
+    /// `author` the id of the connection it came from (see `ClientList::id_of`)
+    pub fn push(&mut self, stamp: Stamp, reply_to: Option<u64>, sender: &str, author: Option<u64>, message: &str) {
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(HistoryEntry {
+            id: stamp.id,
+            time: stamp.time,
+            sender: sender.to_string(),
+            author,
+            message: message.to_string(),
+            reply_to,
+            edited: None,
+            reactions: BTreeMap::new(),
+        });
+    }
+
+    /// # `find`
+    /// The remembered message with the id `id`
+    pub fn find(&self, id: u64) -> Option<&HistoryEntry> {
+        self.entries.iter().find(|entry| entry.id == id)
+    }
+
+    /// # `thread`
+    /// The whole thread the message `id` belongs to, each message with how deep it is nested: the first message of the thread
+    /// (depth 0), then every reply right after the message it replies to, older replies first. `None` if `id` is not remembered.
+    /// A thread whose first message was deleted or forgotten starts at the oldest message still remembered
+    pub fn thread(&self, id: u64) -> Option<Vec<(usize, HistoryEntry)>> {
+        let mut root = self.find(id)?;
+        while let Some(parent) = root.reply_to.and_then(|parent| self.find(parent)) {
+            root = parent;
+        }
+
+        let mut thread = Vec::new();
+        let mut stack = vec![(0, root)];
+        while let Some((depth, entry)) = stack.pop() {
+            // Pushed newest first so the oldest reply comes out first
+            stack.extend(
+                self.entries
+                    .iter()
+                    .rev()
+                    .filter(|reply| reply.reply_to == Some(entry.id))
+                    .map(|reply| (depth + 1, reply)),
+            );
+            thread.push((depth, entry.clone()));
+        }
+        Some(thread)
+    }
+
+    /// # `edit`
+    /// Replaces the text of the message `id`. Returns `false` if it is not remembered
+    pub fn edit(&mut self, id: u64, message: &str) -> bool {
+        match self.entries.iter_mut().find(|entry| entry.id == id) {
+            Some(entry) => {
+                entry.message = message.to_string();
+                entry.edited = Some(Utc::now());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// # `toggle_reaction`
+    /// Adds the reaction of `user` with `emoji` to the message `id`, or takes it away if `user` already reacted with it.
+    /// Returns `Ok(true)` if it was added, `Ok(false)` if it was taken away
+    pub fn toggle_reaction(&mut self, id: u64, user: &str, emoji: &str) -> Result<bool, String> {
+        let entry = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.id == id)
+            .ok_or_else(|| format!("No message {} among the latest public messages", id))?;
+
+        if let Some(users) = entry.reactions.get_mut(emoji) {
+            if let Some(position) = users.iter().position(|reacted| reacted == user) {
+                users.remove(position);
+                if users.is_empty() {
+                    entry.reactions.remove(emoji);
+                }
+                return Ok(false);
+            }
+            users.push(user.to_string());
+            return Ok(true);
+        }
+        if entry.reactions.len() >= MAX_REACTIONS {
+            return Err(format!("A message can have at most {} different reactions", MAX_REACTIONS));
+        }
+        entry.reactions.insert(emoji.to_string(), vec![user.to_string()]);
+        Ok(true)
+    }
+
+    /// # `delete`
+    /// Forgets the message `id`. Returns `false` if it is not remembered
+    pub fn delete(&mut self, id: u64) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|entry| entry.id != id);
+        self.entries.len() != before
+    }
+
+    /// # `since`
+    /// The remembered messages with an id greater than `id`, oldest first
+    pub fn since(&self, id: u64) -> Vec<HistoryEntry> {
+        self.entries.iter().filter(|entry| entry.id > id).cloned().collect()
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new(HISTORY_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A history holding the messages `(id, reply_to)`, sent by `user<id>`
+    fn history(messages: &[(u64, Option<u64>)]) -> History {
+        let mut history = History::new(10);
+        for &(id, reply_to) in messages {
+            let stamp = Stamp { id, time: Utc::now() };
+            history.push(stamp, reply_to, &format!("user{}", id), None, &format!("message {}", id));
+        }
+        history
+    }
+
+    fn ids(thread: Option<Vec<(usize, HistoryEntry)>>) -> Vec<(usize, u64)> {
+        thread.unwrap().iter().map(|(depth, entry)| (*depth, entry.id)).collect()
+    }
+
+    #[test]
+    fn thread_nests_replies_under_their_parent_oldest_first() {
+        // 1 <- 2 <- 4, 1 <- 3, 5 is not part of it
+        let history = history(&[(1, None), (2, Some(1)), (3, Some(1)), (4, Some(2)), (5, None)]);
+        let expected = vec![(0, 1), (1, 2), (2, 4), (1, 3)];
+        // The same thread whichever of its messages is asked for
+        for id in 1..=4 {
+            assert_eq!(ids(history.thread(id)), expected);
+        }
+        assert_eq!(ids(history.thread(5)), vec![(0, 5)]);
+        assert!(history.thread(6).is_none());
+    }
+
+    #[test]
+    fn thread_starts_at_the_oldest_remembered_message() {
+        let mut history = history(&[(1, None), (2, Some(1)), (3, Some(2))]);
+        history.delete(1);
+        assert_eq!(ids(history.thread(3)), vec![(0, 2), (1, 3)]);
+    }
+
+    #[test]
+    fn push_forgets_the_oldest_message_when_full() {
+        let mut history = History::new(2);
+        for id in 1..=3 {
+            history.push(Stamp { id, time: Utc::now() }, None, "azeez", None, "hi");
+        }
+        assert!(history.find(1).is_none());
+        assert_eq!(history.since(0).iter().map(|entry| entry.id).collect::<Vec<u64>>(), vec![2, 3]);
+    }
+
+    #[test]
+    fn toggle_reaction_adds_then_takes_away() {
+        let mut history = history(&[(1, None)]);
+        assert_eq!(history.toggle_reaction(1, "azeez", "👍"), Ok(true));
+        assert_eq!(history.toggle_reaction(1, "bob", "👍"), Ok(true));
+        assert_eq!(history.find(1).unwrap().reactions["👍"], vec!["azeez", "bob"]);
+
+        assert_eq!(history.toggle_reaction(1, "azeez", "👍"), Ok(false));
+        assert_eq!(history.toggle_reaction(1, "bob", "👍"), Ok(false));
+        assert!(history.find(1).unwrap().reactions.is_empty());
+    }
+
+    #[test]
+    fn toggle_reaction_limits_different_emoji() {
+        let mut history = history(&[(1, None)]);
+        for emoji in 0..MAX_REACTIONS {
+            assert_eq!(history.toggle_reaction(1, "azeez", &emoji.to_string()), Ok(true));
+        }
+        assert!(history.toggle_reaction(1, "azeez", "new").is_err());
+        // Joining a reaction that is already there still works
+        assert_eq!(history.toggle_reaction(1, "bob", "0"), Ok(true));
+    }
+
+    #[test]
+    fn toggle_reaction_needs_a_remembered_message() {
+        assert!(history(&[(1, None)]).toggle_reaction(2, "azeez", "👍").is_err());
+    }
+}
@@ -1,15 +1,29 @@
 use colored::{Colorize, ColoredString};
 extern crate chrono;
-use chrono::Local;
+use chrono::{Local, SecondsFormat, Utc};
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, IsTerminal, Write},
+    path::PathBuf,
+    sync::Mutex,
+};
+
+/// Target prefix used for every record emitted by `server_log`
+pub const LOG_TARGET: &str = "azeezd_sockets";
+const REQUEST_TARGET: &str = "azeezd_sockets::request";
+const HANDLED_TARGET: &str = "azeezd_sockets::handled";
 
 /// # `LogMessageType`
 /// The type of message to log. This will prepend the logging function with an appropiate suffix depending on the value of this enum.
 /// - Information: `INF`
+/// - Warning: `WARN`
 /// - IncomingRequest: `REQ`
 /// - EncounteredError: `ERR`
 /// - RequestHandled: `OK`
 pub enum LogMessagType {
     Information,
+    Warning,
     IncomingRequest,
     EncounteredError,
     RequestHandled
@@ -21,21 +35,244 @@ impl LogMessagType {
     fn as_abbreviation(&self) -> ColoredString {
         match self {
             Self::EncounteredError => "ERR".bold().red(),
+            Self::Warning => "WARN".bold().yellow(),
             Self::IncomingRequest => "REQ".bold().yellow(),
             Self::Information => "INFO".bold(),
             Self::RequestHandled => "OK".bold().green()
         }
     }
+
+    /// # `level`
+    /// The `log::Level` the message is emitted at. Requests are debug-level since they are very chatty
+    pub fn level(&self) -> Level {
+        match self {
+            Self::EncounteredError => Level::Error,
+            Self::Warning => Level::Warn,
+            Self::Information => Level::Info,
+            Self::IncomingRequest | Self::RequestHandled => Level::Debug,
+        }
+    }
+
+    /// # `target`
+    /// The `log` target of the message. Requests and handled requests get their own target so they can be told apart from plain debug output
+    fn target(&self) -> &'static str {
+        match self {
+            Self::IncomingRequest => REQUEST_TARGET,
+            Self::RequestHandled => HANDLED_TARGET,
+            _ => LOG_TARGET,
+        }
+    }
 }
 
 /// # `server_log`
-/// Takes a message as `String` and type of log message as `LogMessageType` and prints the message with the appropiate suffix and prepended with current time
+/// Takes a message as `String` and type of log message as `LogMessageType` and emits it through the `log` facade.
+/// Embedding applications see these as normal `log` records, the server binary installs `ServerLogger` to print them.
 pub fn server_log(message: String, log_message_type: LogMessagType) {
-    println!("{} | {}: {}", Local::now().format("%y%m%d %H:%M:%S"), log_message_type.as_abbreviation(), message);
+    log::log!(target: log_message_type.target(), log_message_type.level(), "{}", message);
 }
 
 /// # `client_log`
 /// Takes a message as `String` and type of log message as `LogMessageType` and prints the message with the appropiate suffix
 pub fn client_log(message: String, log_message_type: LogMessagType) {
     println!("{}: {}", log_message_type.as_abbreviation(), message);
-}
\ No newline at end of file
+}
+
+/// # `parse_level`
+/// Parses a threshold such as `info` or `DEBUG` into a `LevelFilter`. Returns `None` for unknown names
+pub fn parse_level(level: &str) -> Option<LevelFilter> {
+    level.parse().ok()
+}
+
+/// # `LogFormat`
+/// How `ServerLogger` renders a record
+/// - Text: `<time> | <ABR>: <message>`, the classic format
+/// - Json: one JSON object per line
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// # `RotatingFile`
+/// Log file that is rotated once it grows past `max_size` bytes. Old files are kept as `<path>.1` up to `<path>.<keep>`
+struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    keep: usize,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_size: u64, keep: usize) -> io::Result<RotatingFile> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile { path, max_size, keep, file, size })
+    }
+
+    /// # `rotated_path`
+    /// Path of the `n`th rotated file
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        PathBuf::from(path)
+    }
+
+    /// # `rotate`
+    /// Shifts `<path>.N` to `<path>.N+1` (dropping the oldest) and starts a fresh file
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.keep == 0 {
+            self.file = File::create(&self.path)?;
+        } else {
+            let _ = fs::remove_file(self.rotated_path(self.keep));
+            for n in (1..self.keep).rev() {
+                let _ = fs::rename(self.rotated_path(n), self.rotated_path(n + 1));
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+            self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let length = line.len() as u64 + 1;
+        if self.size > 0 && self.size + length > self.max_size {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += length;
+        Ok(())
+    }
+}
+
+/// # `ServerLogger`
+/// `log::Log` implementation used by the server binary. Supports text or JSON-lines output, to stdout or to a size-rotated file.
+/// Colors are only used when writing text to a terminal.
+pub struct ServerLogger {
+    format: LogFormat,
+    file: Option<Mutex<RotatingFile>>,
+    colored: bool,
+}
+
+impl ServerLogger {
+    /// # `new`
+    /// Creates a logger that writes text to stdout
+    pub fn new() -> ServerLogger {
+        ServerLogger {
+            format: LogFormat::Text,
+            file: None,
+            colored: io::stdout().is_terminal(),
+        }
+    }
+
+    /// # `format`
+    /// Sets the output format
+    pub fn format(mut self, format: LogFormat) -> ServerLogger {
+        self.format = format;
+        self
+    }
+
+    /// # `file`
+    /// Writes to the file at `path` instead of stdout. The file is rotated once it grows past `max_size` bytes and `keep` old files are kept.
+    /// Returns `Err` if the file could not be opened
+    pub fn file(mut self, path: PathBuf, max_size: u64, keep: usize) -> io::Result<ServerLogger> {
+        self.file = Some(Mutex::new(RotatingFile::open(path, max_size, keep)?));
+        self.colored = false;
+        Ok(self)
+    }
+
+    /// # `install`
+    /// Installs the logger as the global `log` logger with the given threshold.
+    /// The threshold can be changed later on with `log::set_max_level`
+    pub fn install(self, level: LevelFilter) -> Result<(), SetLoggerError> {
+        if !self.colored {
+            colored::control::set_override(false);
+        }
+        log::set_boxed_logger(Box::new(self))?;
+        log::set_max_level(level);
+        Ok(())
+    }
+
+    /// # `abbreviation`
+    /// The classic `ERR`/`REQ`/`OK`... tag of a record
+    fn abbreviation(record: &Record) -> &'static str {
+        match record.target() {
+            REQUEST_TARGET => "REQ",
+            HANDLED_TARGET => "OK",
+            _ => match record.level() {
+                Level::Error => "ERR",
+                Level::Warn => "WARN",
+                Level::Info => "INFO",
+                Level::Debug => "DEBUG",
+                Level::Trace => "TRACE",
+            },
+        }
+    }
+
+    fn render(&self, record: &Record) -> String {
+        let abbreviation = Self::abbreviation(record);
+        match self.format {
+            LogFormat::Json => serde_json::json!({
+                "time": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+                "level": record.level().as_str().to_lowercase(),
+                "kind": abbreviation,
+                "target": record.target(),
+                "message": record.args().to_string(),
+            })
+            .to_string(),
+            LogFormat::Text => {
+                let tag = if self.colored {
+                    match abbreviation {
+                        "ERR" => abbreviation.bold().red(),
+                        "WARN" | "REQ" => abbreviation.bold().yellow(),
+                        "OK" => abbreviation.bold().green(),
+                        _ => abbreviation.bold(),
+                    }
+                    .to_string()
+                } else {
+                    abbreviation.to_string()
+                };
+                format!("{} | {}: {}", Local::now().format("%y%m%d %H:%M:%S"), tag, record.args())
+            }
+        }
+    }
+}
+
+impl Default for ServerLogger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Log for ServerLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = self.render(record);
+        match &self.file {
+            Some(file) => {
+                if let Ok(mut file) = file.lock() {
+                    if let Err(e) = file.write_line(&line) {
+                        eprintln!("Error \"{}\" writing to log file", e);
+                    }
+                }
+            }
+            None => println!("{}", line),
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &self.file {
+            if let Ok(mut file) = file.lock() {
+                let _ = file.file.flush();
+            }
+        }
+    }
+}
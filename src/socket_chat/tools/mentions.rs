@@ -0,0 +1,110 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use super::{ClientList, Stamp};
+use crate::socket_chat::resposne_type;
+
+/// Most mentions kept for one offline user, the oldest ones are dropped first
+pub const MAX_QUEUED_MENTIONS: usize = 50;
+
+/// # `Mentions`
+/// Tells users they were `@mentioned` in a public message. Users that are offline get told when they join next,
+/// as long as they joined once since the server started
+#[derive(Default)]
+pub struct Mentions {
+    /// Everyone that joined since the server started
+    known: HashSet<String>,
+    /// Mentions waiting for users that are offline, oldest first
+    queued: HashMap<String, VecDeque<String>>,
+}
+
+impl Mentions {
+    /// # `new`
+    pub fn new() -> Mentions {
+        Mentions::default()
+    }
+
+    /// # `joined`
+    /// Remembers that `name` joined and returns the mentions queued for them while they were offline
+    pub fn joined(&mut self, name: &str) -> Vec<String> {
+        self.known.insert(name.to_string());
+        self.queued.remove(name).map(Vec::from).unwrap_or_default()
+    }
+
+    /// # `renamed`
+    /// Follows a client from `old_name` to `new_name`: the old name is forgotten, so later mentions of it are not queued for whoever
+    /// takes it next, and the mentions still queued under it are returned to be sent to the client
+    pub fn renamed(&mut self, old_name: &str, new_name: &str) -> Vec<String> {
+        self.known.remove(old_name);
+        self.known.insert(new_name.to_string());
+        self.queued.remove(old_name).map(Vec::from).unwrap_or_default()
+    }
+
+    /// # `notify`
+    /// Finds the `@name`s in a public message and sends each of them `MEN <id> <time> <sender> <text>`, or queues it if they are offline.
+    /// Punctuation right after a name does not count (`@azeez,`) unless it is part of the name. Senders do not mention themselves
+    pub fn notify(&mut self, clients: &mut ClientList, stamp: Stamp, sender: &str, message: &str) {
+        let notification = format!("{} {} {} {}", resposne_type::MENTION, stamp, sender, message);
+        let mut mentioned = HashSet::new();
+        for names in candidates(message) {
+            let name = names
+                .into_iter()
+                .find(|name| clients.name_exists(&name.to_string()).is_some() || self.known.contains(*name));
+            let name = match name {
+                Some(name) if name != sender && mentioned.insert(name) => name.to_string(),
+                _ => continue,
+            };
+
+            if clients.name_exists(&name).is_some() {
+                let _ = clients.send_to(&name, &notification);
+            } else {
+                let queue = self.queued.entry(name).or_default();
+                if queue.len() == MAX_QUEUED_MENTIONS {
+                    queue.pop_front();
+                }
+                queue.push_back(notification.clone());
+            }
+        }
+    }
+}
+
+/// # `mentions`
+/// Whether `message` mentions `name`, found the same way the server finds mentions
+pub fn mentions(message: &str, name: &str) -> bool {
+    candidates(message).any(|names| names.contains(&name))
+}
+
+/// # `candidates`
+/// The names every `@word` of a message may mean: the word itself and the word without the punctuation after it
+fn candidates(message: &str) -> impl Iterator<Item = [&str; 2]> {
+    message.split_whitespace().filter_map(|word| word.strip_prefix('@')).map(|word| {
+        let trimmed = word.trim_end_matches(|c: char| !c.is_alphanumeric());
+        [word, trimmed]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mentions_ignore_punctuation_after_the_name() {
+        assert!(mentions("hi @azeez, how are you", "azeez"));
+        assert!(mentions("@azeez!", "azeez"));
+        assert!(mentions("ask @a.b", "a.b"));
+        assert!(!mentions("azeez without the at", "azeez"));
+        assert!(!mentions("email me at azeez@example.com", "example.com"));
+        assert!(!mentions("@azeezd", "azeez"));
+    }
+
+    #[test]
+    fn renamed_moves_the_queue_and_forgets_the_old_name() {
+        let mut mentions = Mentions::new();
+        mentions.joined("alice");
+        mentions.queued.entry("alice".to_string()).or_default().push_back("MEN 1".to_string());
+
+        assert_eq!(mentions.renamed("alice", "alicia"), vec!["MEN 1".to_string()]);
+        assert!(!mentions.known.contains("alice"));
+        assert!(mentions.known.contains("alicia"));
+        assert!(mentions.joined("alice").is_empty());
+    }
+}
@@ -0,0 +1,232 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        LockResult, Mutex, MutexGuard, TryLockError,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::{server_log, LogMessagType};
+
+/// Largest scrape request that is read, request line and headers together
+const MAX_REQUEST_SIZE: u64 = 16 * 1024;
+/// Upper bounds (in seconds) of the broadcast latency histogram buckets
+const LATENCY_BUCKETS: [f64; 8] = [0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5];
+
+/// # `Histogram`
+/// Cumulative Prometheus style histogram over `LATENCY_BUCKETS`
+struct Histogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Histogram {
+        Histogram {
+            buckets: [const { AtomicU64::new(0) }; LATENCY_BUCKETS.len()],
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (bucket, bound) in self.buckets.iter().zip(LATENCY_BUCKETS) {
+            if seconds <= bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+/// # `Metrics`
+/// Counters and gauges of a running server. There is a single global instance, `METRICS`
+pub struct Metrics {
+    connected_clients: AtomicU64,
+    messages: Mutex<BTreeMap<String, u64>>,
+    broadcast_latency: Histogram,
+    dropped_writes: AtomicU64,
+    denied_connections: AtomicU64,
+    lock_contentions: AtomicU64,
+    lock_wait_micros: AtomicU64,
+}
+
+/// The server's metrics
+pub static METRICS: Metrics = Metrics {
+    connected_clients: AtomicU64::new(0),
+    messages: Mutex::new(BTreeMap::new()),
+    broadcast_latency: Histogram::new(),
+    dropped_writes: AtomicU64::new(0),
+    denied_connections: AtomicU64::new(0),
+    lock_contentions: AtomicU64::new(0),
+    lock_wait_micros: AtomicU64::new(0),
+};
+
+impl Metrics {
+    /// # `set_connected_clients`
+    /// Sets the connected clients gauge
+    pub fn set_connected_clients(&self, count: usize) {
+        self.connected_clients.store(count as u64, Ordering::Relaxed);
+    }
+
+    /// # `count_message`
+    /// Counts a request of the given type (`MSG`, `CMD`...)
+    pub fn count_message(&self, message_type: &str) {
+        if let Ok(mut messages) = self.messages.lock() {
+            *messages.entry(message_type.to_string()).or_insert(0) += 1;
+        }
+    }
+
+    /// # `observe_broadcast`
+    /// Records how long sending a message to every client took
+    pub fn observe_broadcast(&self, duration: Duration) {
+        self.broadcast_latency.observe(duration);
+    }
+
+    /// # `count_dropped_write`
+    /// Counts a message that could not be written to a client
+    pub fn count_dropped_write(&self) {
+        self.dropped_writes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// # `count_denied_connection`
+    /// Counts a connection that was refused
+    pub fn count_denied_connection(&self) {
+        self.denied_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// # `render`
+    /// Renders every metric in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let counter = |out: &mut String, name: &str, help: &str, kind: &str, value: String| {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}\n{} {}", name, help, name, kind, name, value);
+        };
+
+        counter(
+            &mut out,
+            "chat_connected_clients",
+            "Number of clients currently connected.",
+            "gauge",
+            self.connected_clients.load(Ordering::Relaxed).to_string(),
+        );
+
+        let _ = writeln!(out, "# HELP chat_messages_total Requests received by type.\n# TYPE chat_messages_total counter");
+        if let Ok(messages) = self.messages.lock() {
+            for (message_type, count) in messages.iter() {
+                let _ = writeln!(out, "chat_messages_total{{type=\"{}\"}} {}", message_type, count);
+            }
+        }
+
+        let _ = writeln!(out, "# HELP chat_broadcast_duration_seconds Time spent sending a message to every client.\n# TYPE chat_broadcast_duration_seconds histogram");
+        for (bucket, bound) in self.broadcast_latency.buckets.iter().zip(LATENCY_BUCKETS) {
+            let _ = writeln!(out, "chat_broadcast_duration_seconds_bucket{{le=\"{}\"}} {}", bound, bucket.load(Ordering::Relaxed));
+        }
+        let count = self.broadcast_latency.count.load(Ordering::Relaxed);
+        let _ = writeln!(out, "chat_broadcast_duration_seconds_bucket{{le=\"+Inf\"}} {}", count);
+        let _ = writeln!(out, "chat_broadcast_duration_seconds_sum {}", self.broadcast_latency.sum_micros.load(Ordering::Relaxed) as f64 / 1e6);
+        let _ = writeln!(out, "chat_broadcast_duration_seconds_count {}", count);
+
+        counter(
+            &mut out,
+            "chat_dropped_writes_total",
+            "Messages that could not be written to a client.",
+            "counter",
+            self.dropped_writes.load(Ordering::Relaxed).to_string(),
+        );
+        counter(
+            &mut out,
+            "chat_denied_connections_total",
+            "Connections that were refused.",
+            "counter",
+            self.denied_connections.load(Ordering::Relaxed).to_string(),
+        );
+        counter(
+            &mut out,
+            "chat_client_list_lock_contentions_total",
+            "Times the client list lock was already held when acquiring it.",
+            "counter",
+            self.lock_contentions.load(Ordering::Relaxed).to_string(),
+        );
+        counter(
+            &mut out,
+            "chat_client_list_lock_wait_seconds_total",
+            "Time spent waiting for the client list lock.",
+            "counter",
+            (self.lock_wait_micros.load(Ordering::Relaxed) as f64 / 1e6).to_string(),
+        );
+
+        out
+    }
+}
+
+/// # `metered_lock`
+/// Locks the mutex like `Mutex::lock` while recording in `METRICS` whether (and for how long) it had to wait for it
+pub fn metered_lock<T>(mutex: &Mutex<T>) -> LockResult<MutexGuard<'_, T>> {
+    match mutex.try_lock() {
+        Ok(guard) => Ok(guard),
+        Err(TryLockError::Poisoned(e)) => Err(e),
+        Err(TryLockError::WouldBlock) => {
+            METRICS.lock_contentions.fetch_add(1, Ordering::Relaxed);
+            let start = Instant::now();
+            let guard = mutex.lock();
+            METRICS.lock_wait_micros.fetch_add(start.elapsed().as_micros() as u64, Ordering::Relaxed);
+            guard
+        }
+    }
+}
+
+/// # `serve_metrics`
+/// Binds to `address` and serves `METRICS` on `GET /metrics` from a background thread.
+/// Returns `Err` if the address could not be bound
+pub fn serve_metrics(address: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    server_log(format!("Serving metrics on http://{}/metrics", address), LogMessagType::Information);
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = answer_scrape(stream) {
+                server_log(format!("Error \"{}\" answering metrics request", e), LogMessagType::EncounteredError);
+            }
+        }
+    });
+    Ok(())
+}
+
+/// # `answer_scrape`
+/// Reads one HTTP request from the stream and answers it
+fn answer_scrape(mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    // A request that never ends stops at the limit instead of filling the memory
+    let mut reader = BufReader::new(&stream).take(MAX_REQUEST_SIZE);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // Skip the headers, closing with unread data would reset the connection
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_ascii_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", METRICS.render()),
+        _ => ("404 Not Found", "Not Found\n".to_string()),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
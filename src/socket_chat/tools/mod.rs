@@ -1,4 +1,14 @@
+mod audit;
+mod ban_list;
 mod client_list;
+mod config;
+mod history;
 mod logger;
+mod mentions;
+mod metrics;
 
-pub use self::{client_list::ClientList, logger::*};
+pub use self::{
+    audit::*, ban_list::BanList, client_list::{ClientList, Presence}, config::{Announcement, ApiToken, Config}, history::*, logger::*,
+    mentions::*, metrics::*,
+};
+pub(crate) use self::client_list::{ClientName, Connection};
@@ -0,0 +1,174 @@
+use std::{
+    fmt, fs,
+    io::{self, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+};
+
+/// What an address starts with when it is the path of a Unix domain socket (`unix:/tmp/chat.sock`)
+pub const UNIX_PREFIX: &str = "unix:";
+
+/// # `Address`
+/// Where a server listens, either `host:port` or `unix:<path>`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Address {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Tcp(address) => write!(f, "{}", address),
+            Address::Unix(path) => write!(f, "{}{}", UNIX_PREFIX, path.display()),
+        }
+    }
+}
+
+/// # `Stream`
+/// A connection between a client and the server, over TCP or a Unix domain socket
+#[derive(Debug)]
+pub enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Stream {
+    /// # `connect`
+    /// Connects to `address`, which is `host:port` or `unix:<path>`
+    pub fn connect(address: &str) -> io::Result<Stream> {
+        match address.strip_prefix(UNIX_PREFIX) {
+            Some(path) => UnixStream::connect(path).map(Stream::Unix),
+            None => TcpStream::connect(address).map(Stream::Tcp),
+        }
+    }
+
+    /// # `try_clone`
+    /// Another handle to the same connection, like `TcpStream::try_clone`
+    pub fn try_clone(&self) -> io::Result<Stream> {
+        match self {
+            Stream::Tcp(stream) => stream.try_clone().map(Stream::Tcp),
+            Stream::Unix(stream) => stream.try_clone().map(Stream::Unix),
+        }
+    }
+
+    /// # `shutdown`
+    /// Shuts down reading, writing or both halves of the connection
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.shutdown(how),
+            Stream::Unix(stream) => stream.shutdown(how),
+        }
+    }
+
+    /// # `peer_addr`
+    /// The address of the other end. `None` on a Unix socket, where peers have no address
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Stream::Tcp(stream) => stream.peer_addr().ok(),
+            Stream::Unix(_) => None,
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buf),
+            Stream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buf),
+            Stream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+impl From<TcpStream> for Stream {
+    fn from(stream: TcpStream) -> Self {
+        Stream::Tcp(stream)
+    }
+}
+
+impl From<UnixStream> for Stream {
+    fn from(stream: UnixStream) -> Self {
+        Stream::Unix(stream)
+    }
+}
+
+/// # `Listener`
+/// Accepts `Stream`s on a TCP port or a Unix domain socket. The socket file of a Unix listener is removed when it is dropped
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    /// # `bind`
+    /// Listens on `address`, which is `host:port` or `unix:<path>`
+    pub fn bind(address: &str) -> io::Result<Listener> {
+        match address.strip_prefix(UNIX_PREFIX) {
+            Some(path) => bind_unix(Path::new(path)).map(|listener| Listener::Unix(listener, PathBuf::from(path))),
+            None => TcpListener::bind(address).map(Listener::Tcp),
+        }
+    }
+
+    /// # `local_addr`
+    /// The address the listener is bound to
+    pub fn local_addr(&self) -> io::Result<Address> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().map(Address::Tcp),
+            Listener::Unix(_, path) => Ok(Address::Unix(path.clone())),
+        }
+    }
+
+    /// # `incoming`
+    /// Accepted connections, one at a time, like `TcpListener::incoming`
+    pub fn incoming(&self) -> impl Iterator<Item = io::Result<Stream>> + '_ {
+        std::iter::repeat_with(move || match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Stream::Tcp(stream)),
+            Listener::Unix(listener, _) => listener.accept().map(|(stream, _)| Stream::Unix(stream)),
+        })
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix(_, path) = self {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// # `bind_unix`
+/// Binds a Unix domain socket at `path`. A stale socket file from an earlier run is replaced, but not one a running server still listens on
+pub(crate) fn bind_unix(path: &Path) -> io::Result<UnixListener> {
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, "Socket is in use by another server"));
+        }
+        fs::remove_file(path)?;
+    }
+    UnixListener::bind(path)
+}
+
+/// # `describe_peer`
+/// How a client's address shows up in logs, Unix socket clients have none
+pub(crate) fn describe_peer(address: Option<SocketAddr>) -> String {
+    address
+        .map(|address| address.to_string())
+        .unwrap_or_else(|| "unix socket".to_string())
+}
@@ -0,0 +1,582 @@
+use chrono::{DateTime, Local, Utc};
+use crossterm::{
+    cursor::MoveTo,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::{Attribute, Color, Print, ResetColor, SetAttribute, SetForegroundColor},
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use std::{
+    collections::HashMap,
+    io::{self, Stdout, Write},
+    time::{Duration, Instant},
+};
+
+use super::chat_client::{ChatClient, ChatEvent, Reactions, TypingUsers};
+use super::tools::Presence;
+use super::commands;
+
+/// Width of the user list on the right, it is hidden on narrow terminals
+const SIDEBAR_WIDTH: u16 = 20;
+/// How long to wait for a key press before checking for new responses
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How often to try to reconnect after the connection was lost
+const RECONNECT_ATTEMPTS: u32 = 5;
+/// How long to wait before every reconnection attempt
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// # `LineKind`
+/// What a line in the scrollback is, decides how it is colored
+enum LineKind {
+    Chat(String),
+    Whisper(String),
+    /// A message of a thread asked for with `/thread`, with its sender and how deep it is nested
+    Thread(String, usize),
+    Info,
+    System,
+    Error,
+}
+
+/// # `Line`
+/// A line in the scrollback
+struct Line {
+    time: String,
+    kind: LineKind,
+    text: String,
+    /// Id of the public message shown, lines of edited or deleted messages are changed in place
+    id: Option<u64>,
+    /// Id of the message this one replies to
+    reply_to: Option<u64>,
+    /// Reactions on the message, shown in a row under it
+    reactions: Reactions,
+    /// Whether the message mentions this client, it is highlighted then
+    mentioned: bool,
+}
+
+/// # `Tui`
+/// Full-screen terminal front-end for `ChatClient`: a scrollback pane, a user list, a status bar and an input line with editing and history
+pub struct Tui {
+    client: ChatClient,
+    lines: Vec<Line>,
+    users: Vec<String>,
+    input: Vec<char>,
+    cursor: usize,
+    history: Vec<String>,
+    history_index: Option<usize>,
+    scroll: usize,
+    connected: bool,
+    /// The next reconnection attempt and when it is due, while the connection is lost
+    reconnect: Option<(u32, Instant)>,
+    quit: bool,
+    /// Whether to ring the terminal bell when mentioned
+    bell: bool,
+    /// A mention arrived since the last redraw
+    ring: bool,
+    /// Who else is writing a message, shown in the status bar
+    typing: TypingUsers,
+    /// Who is away or busy, shown in the user list
+    presence: HashMap<String, Presence>,
+}
+
+/// # `TerminalGuard`
+/// Puts the terminal in raw mode on the alternate screen and restores it when dropped, also when panicking
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enter() -> io::Result<TerminalGuard> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen)?;
+        Ok(TerminalGuard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+impl Tui {
+    /// # `new`
+    /// Takes a connected `ChatClient` and wraps it in a TUI
+    pub fn new(client: ChatClient) -> Tui {
+        Tui {
+            client,
+            lines: Vec::new(),
+            users: Vec::new(),
+            input: Vec::new(),
+            cursor: 0,
+            history: Vec::new(),
+            history_index: None,
+            scroll: 0,
+            connected: true,
+            reconnect: None,
+            quit: false,
+            bell: false,
+            ring: false,
+            typing: TypingUsers::default(),
+            presence: HashMap::new(),
+        }
+    }
+
+    /// # `bell`
+    /// Rings the terminal bell whenever a message mentions this client
+    pub fn bell(mut self, bell: bool) -> Tui {
+        self.bell = bell;
+        self
+    }
+
+    /// # `init`
+    /// Runs the TUI. This will block until the user quits
+    pub fn init(&mut self) -> io::Result<()> {
+        let _guard = TerminalGuard::enter()?;
+        let mut stdout = io::stdout();
+
+        // Fill the user list
+        if let Err(e) = self.client.command(super::commands::WHO) {
+            self.push(LineKind::Error, e.to_string());
+        }
+        self.push(LineKind::Info, "Connected! Esc or Ctrl+C to quit, PgUp/PgDn to scroll".to_string());
+
+        // Only redraw when something changed, clearing the screen every poll flickers
+        let mut dirty = true;
+        let mut shown_typing = None;
+        while !self.quit {
+            dirty |= self.receive();
+            dirty |= self.try_reconnect();
+            // Typing indicators also run out without any event
+            dirty |= self.typing.summary() != shown_typing;
+            if dirty {
+                self.draw(&mut stdout)?;
+                shown_typing = self.typing.summary();
+                dirty = false;
+            }
+            if self.ring {
+                execute!(stdout, Print('\u{7}'))?;
+                self.ring = false;
+            }
+
+            if event::poll(POLL_INTERVAL)? {
+                match event::read()? {
+                    Event::Key(key) if key.kind == KeyEventKind::Press => {
+                        self.handle_key(key);
+                        dirty = true;
+                    }
+                    Event::Resize(_, _) => dirty = true,
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, kind: LineKind, text: String) {
+        self.push_at(kind, text, Utc::now(), None);
+    }
+
+    /// # `push_at`
+    /// Adds a line that happened at `time`, shown in local time. `id` is the id of a public message
+    fn push_at(&mut self, kind: LineKind, text: String, time: DateTime<Utc>, id: Option<u64>) {
+        self.lines.push(Line {
+            time: time.with_timezone(&Local).format("%H:%M").to_string(),
+            kind,
+            text,
+            id,
+            reply_to: None,
+            reactions: Reactions::default(),
+            mentioned: false,
+        });
+        // Keep the view where it is if the user scrolled up
+        if self.scroll > 0 {
+            self.scroll += 1;
+        }
+    }
+
+    /// # `receive`
+    /// Takes every event that arrived since the last call and adds it to the scrollback. Returns whether anything changed
+    fn receive(&mut self) -> bool {
+        let events: Vec<ChatEvent> = self.client.poll_events().collect();
+        let changed = !events.is_empty();
+        for event in events {
+            self.typing.update(&event);
+            match event {
+                ChatEvent::Disconnected => {
+                    self.connected = false;
+                    self.push(LineKind::Error, "You have been disconnected".to_string());
+                }
+                ChatEvent::Closed => {
+                    if self.connected {
+                        self.connected = false;
+                        self.push(LineKind::Error, "Connection to the server was closed, reconnecting".to_string());
+                        self.reconnect = Some((1, Instant::now() + RECONNECT_DELAY));
+                    }
+                }
+                ChatEvent::PublicMessage { id, time, reply_to, sender, message } => {
+                    self.push_at(LineKind::Chat(sender), message, time, Some(id));
+                    if let Some(line) = self.lines.last_mut() {
+                        line.reply_to = reply_to;
+                    }
+                }
+                ChatEvent::ThreadMessage { depth, id, time, sender, message } => {
+                    if depth == 0 {
+                        self.push(LineKind::Info, format!("Thread of #{}", id));
+                    }
+                    self.push_at(LineKind::Thread(sender, depth), message, time, Some(id));
+                }
+                ChatEvent::PrivateMessage { time, sender, message, .. } => self.push_at(LineKind::Whisper(sender), message, time, None),
+                ChatEvent::MessageEdited { id, message, .. } => {
+                    if let Some(line) = self.lines.iter_mut().find(|line| line.id == Some(id)) {
+                        line.text = format!("{} (edited)", message);
+                    }
+                }
+                ChatEvent::MessageDeleted(id) => {
+                    if let Some(line) = self.lines.iter_mut().find(|line| line.id == Some(id)) {
+                        line.text = "[message deleted]".to_string();
+                        line.reactions = Reactions::default();
+                    }
+                }
+                ChatEvent::ReactionAdded { id, .. } | ChatEvent::ReactionRemoved { id, .. } => {
+                    if let Some(line) = self.lines.iter_mut().find(|line| line.id == Some(id)) {
+                        line.reactions.update(&event);
+                    }
+                }
+                ChatEvent::Mentioned { id, time, sender, message } => {
+                    // Mentions made while offline were never shown, they get a line of their own
+                    if !self.lines.iter().any(|line| line.id == Some(id)) {
+                        self.push_at(LineKind::Chat(sender), message, time, Some(id));
+                    }
+                    if let Some(line) = self.lines.iter_mut().find(|line| line.id == Some(id)) {
+                        line.mentioned = true;
+                    }
+                    self.ring |= self.bell;
+                }
+                ChatEvent::UserJoined(name) => {
+                    self.push(LineKind::Info, format!("{} joined the server!", name));
+                    if !self.users.contains(&name) {
+                        self.users.push(name);
+                        self.users.sort();
+                    }
+                }
+                ChatEvent::UserLeft(name) => {
+                    self.push(LineKind::Info, format!("{} left the server!", name));
+                    self.users.retain(|user| *user != name);
+                    self.presence.remove(&name);
+                }
+                ChatEvent::NickChanged { old, new } => {
+                    self.push(LineKind::Info, format!("{} is now known as {}", old, new));
+                    if let Some(user) = self.users.iter_mut().find(|user| **user == old) {
+                        *user = new.clone();
+                        self.users.sort();
+                    }
+                    if let Some(presence) = self.presence.remove(&old) {
+                        self.presence.insert(new, presence);
+                    }
+                }
+                ChatEvent::System(message) => self.push(LineKind::System, message),
+                ChatEvent::UserList(mut names) => {
+                    names.sort();
+                    self.users = names;
+                }
+                ChatEvent::Presence { name, presence, .. } => {
+                    // `/who` sends everyone's presence, only changes get a line
+                    if self.presence.get(&name).unwrap_or(&Presence::Online) != &presence {
+                        self.push(LineKind::Info, format!("{} is {}", name, presence));
+                    }
+                    self.presence.insert(name, presence);
+                }
+                ChatEvent::Error(reason) | ChatEvent::Rejected { reason, .. } => self.push(LineKind::Error, reason),
+                ChatEvent::Delivered { recipient, .. } => self.push(LineKind::Info, format!("Delivered to {}", recipient)),
+                ChatEvent::Acknowledged(_) => {}
+                ChatEvent::Typing { .. } => {}
+                ChatEvent::Unknown(_) => {}
+            }
+        }
+        changed
+    }
+
+    /// # `try_reconnect`
+    /// Makes the reconnection attempt that is due, if any, without blocking the screen. The unacknowledged requests are sent again
+    /// once it works, the same way the line client does. Returns whether anything changed
+    fn try_reconnect(&mut self) -> bool {
+        let attempt = match self.reconnect {
+            Some((attempt, due)) if Instant::now() >= due => attempt,
+            _ => return false,
+        };
+        match self.client.reconnect() {
+            Ok(resent) => {
+                self.connected = true;
+                self.reconnect = None;
+                self.push(LineKind::Info, format!("Reconnected! Sent {} unacknowledged request(s) again", resent));
+                if let Err(e) = self.client.command(super::commands::WHO) {
+                    self.push(LineKind::Error, e.to_string());
+                }
+            }
+            Err(e) if attempt < RECONNECT_ATTEMPTS => {
+                self.push(LineKind::Error, format!("Reconnecting ({}/{}) failed \"{}\"", attempt, RECONNECT_ATTEMPTS, e));
+                self.reconnect = Some((attempt + 1, Instant::now() + RECONNECT_DELAY));
+            }
+            Err(e) => {
+                self.push(LineKind::Error, format!("Could not reconnect \"{}\", Esc to quit", e));
+                self.reconnect = None;
+            }
+        }
+        true
+    }
+
+    /// # `handle_key`
+    /// Edits the input line, walks the history, scrolls or sends depending on the key
+    fn handle_key(&mut self, key: KeyEvent) {
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        let before = self.input.clone();
+        match key.code {
+            KeyCode::Esc => self.exit(),
+            KeyCode::Char('c') if control => self.exit(),
+            KeyCode::Char('a') if control => self.cursor = 0,
+            KeyCode::Char('e') if control => self.cursor = self.input.len(),
+            KeyCode::Char('u') if control => {
+                self.input.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            KeyCode::Char(c) => {
+                self.input.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.input.remove(self.cursor);
+            }
+            KeyCode::Delete if self.cursor < self.input.len() => {
+                self.input.remove(self.cursor);
+            }
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(self.input.len()),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.input.len(),
+            KeyCode::Up => self.walk_history(true),
+            KeyCode::Down => self.walk_history(false),
+            KeyCode::PageUp => self.scroll = (self.scroll + 5).min(self.lines.len().saturating_sub(1)),
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(5),
+            KeyCode::Enter => self.submit(),
+            _ => {}
+        }
+        if self.input != before && self.connected && !self.quit {
+            self.update_typing();
+        }
+    }
+
+    /// # `update_typing`
+    /// Sends or takes back the typing indicator after the input changed. A whisper only tells its target, commands other
+    /// than `/reply` tell nobody
+    fn update_typing(&mut self) {
+        let input: String = self.input.iter().collect();
+        let mut words = input.trim_start().split(' ');
+        let result = match (words.next().unwrap_or_default(), words.next(), words.next()) {
+            ("", _, _) => self.client.stopped_typing(),
+            (command, Some(target), Some(_)) if !target.is_empty() && [commands::WHISPER, "/whisper"].contains(&command) => {
+                self.client.typing(Some(target))
+            }
+            (commands::REPLY, Some(_), Some(_)) => self.client.typing(None),
+            (command, _, _) if command.starts_with('/') => self.client.stopped_typing(),
+            _ => self.client.typing(None),
+        };
+        if let Err(e) = result {
+            self.push(LineKind::Error, e.to_string());
+        }
+    }
+
+    /// # `walk_history`
+    /// Replaces the input with the previous (`back`) or next entry of the history
+    fn walk_history(&mut self, back: bool) {
+        if self.history.is_empty() {
+            return;
+        }
+        self.history_index = match (self.history_index, back) {
+            (None, true) => Some(self.history.len() - 1),
+            (None, false) => None,
+            (Some(i), true) => Some(i.saturating_sub(1)),
+            (Some(i), false) if i + 1 < self.history.len() => Some(i + 1),
+            (Some(_), false) => None,
+        };
+        self.input = match self.history_index {
+            Some(i) => self.history[i].chars().collect(),
+            None => Vec::new(),
+        };
+        self.cursor = self.input.len();
+    }
+
+    /// # `submit`
+    /// Sends the input line to the server
+    fn submit(&mut self) {
+        let line: String = self.input.iter().collect();
+        self.input.clear();
+        self.cursor = 0;
+        self.history_index = None;
+        self.scroll = 0;
+        if line.trim().is_empty() {
+            return;
+        }
+        self.history.push(line.clone());
+
+        if line.trim() == super::commands::LOGOUT {
+            self.exit();
+        } else if let Err(e) = self.client.send_input(&line) {
+            self.push(LineKind::Error, e.to_string());
+        }
+    }
+
+    /// # `exit`
+    /// Logs out (if still connected) and stops the TUI
+    fn exit(&mut self) {
+        if self.connected {
+            let _ = self.client.logout();
+        }
+        self.quit = true;
+    }
+
+    /// # `draw`
+    /// Redraws the whole screen
+    fn draw(&self, stdout: &mut Stdout) -> io::Result<()> {
+        let (width, height) = terminal::size()?;
+        if height < 3 {
+            return Ok(());
+        }
+        let sidebar = if width >= SIDEBAR_WIDTH * 3 { SIDEBAR_WIDTH } else { 0 };
+        let chat_width = (width - sidebar) as usize;
+        let chat_height = (height - 2) as usize;
+
+        queue!(stdout, Clear(ClearType::All))?;
+
+        // == SCROLLBACK ==
+        let mut rows: Vec<(Color, String, String, bool)> = Vec::new();
+        for line in self.lines.iter() {
+            let (color, prefix) = match &line.kind {
+                LineKind::Chat(sender) if line.mentioned => match line.id {
+                    Some(id) => (Color::Yellow, format!("{} #{} @ {}> ", line.time, id, sender)),
+                    None => (Color::Yellow, format!("{} @ {}> ", line.time, sender)),
+                },
+                LineKind::Chat(sender) => match (line.id, line.reply_to) {
+                    (Some(id), Some(parent)) => (Color::Blue, format!("{} #{} ↳ #{} {}> ", line.time, id, parent, sender)),
+                    (Some(id), None) => (Color::Blue, format!("{} #{} {}> ", line.time, id, sender)),
+                    (None, _) => (Color::Blue, format!("{} {}> ", line.time, sender)),
+                },
+                LineKind::Thread(sender, depth) => {
+                    let indent = if *depth == 0 { String::new() } else { format!("{}↳ ", "  ".repeat(depth - 1)) };
+                    (Color::Cyan, format!("{} #{} {}{}> ", line.time, line.id.unwrap_or_default(), indent, sender))
+                }
+                LineKind::Whisper(sender) => (Color::Magenta, format!("{} {} whispered: ", line.time, sender)),
+                LineKind::Info => (Color::DarkGrey, format!("{} ", line.time)),
+                LineKind::System => (Color::Yellow, format!("{} [SYSTEM] ", line.time)),
+                LineKind::Error => (Color::Red, format!("{} ", line.time)),
+            };
+            let mut text: Vec<char> = line.text.chars().collect();
+            let first_width = chat_width.saturating_sub(prefix.chars().count()).max(1);
+            let rest: Vec<char> = text.split_off(first_width.min(text.len()));
+            rows.push((color, prefix, text.into_iter().collect(), line.mentioned));
+            for chunk in rest.chunks(chat_width.max(1)) {
+                rows.push((color, String::new(), chunk.iter().collect(), line.mentioned));
+            }
+            if !line.reactions.is_empty() {
+                let summary: String = line.reactions.summary().chars().take(chat_width.saturating_sub(4)).collect();
+                rows.push((Color::DarkGrey, "    ".to_string(), summary, false));
+            }
+        }
+        let end = rows.len().saturating_sub(self.scroll);
+        let start = end.saturating_sub(chat_height);
+        for (y, (color, prefix, text, highlighted)) in rows[start..end].iter().enumerate() {
+            queue!(
+                stdout,
+                MoveTo(0, y as u16),
+                SetForegroundColor(*color),
+                SetAttribute(Attribute::Bold),
+                Print(prefix),
+                SetAttribute(Attribute::Reset),
+                ResetColor
+            )?;
+            // Messages that mention this client stay bold
+            if *highlighted {
+                queue!(stdout, SetAttribute(Attribute::Bold), Print(text), SetAttribute(Attribute::Reset))?;
+            } else {
+                queue!(stdout, Print(text))?;
+            }
+        }
+
+        // == USER LIST ==
+        if sidebar > 0 {
+            let inner = (sidebar - 2) as usize;
+            for y in 0..chat_height as u16 {
+                queue!(stdout, MoveTo(width - sidebar, y), SetForegroundColor(Color::DarkGrey), Print("│"), ResetColor)?;
+            }
+            queue!(
+                stdout,
+                MoveTo(width - sidebar + 2, 0),
+                SetAttribute(Attribute::Bold),
+                Print(format!("Users ({})", self.users.len())),
+                SetAttribute(Attribute::Reset)
+            )?;
+            for (y, user) in self.users.iter().take(chat_height.saturating_sub(1)).enumerate() {
+                // Away and busy users are greyed out with their state after the name
+                let presence = self.presence.get(user).filter(|presence| **presence != Presence::Online);
+                let label = match presence {
+                    Some(presence) => format!("{} ({})", user, presence.state()),
+                    None => user.clone(),
+                };
+                let name: String = label.chars().take(inner).collect();
+                queue!(stdout, MoveTo(width - sidebar + 2, y as u16 + 1))?;
+                if *user == self.client.name() {
+                    queue!(stdout, SetForegroundColor(Color::Green), Print(name), ResetColor)?;
+                } else if presence.is_some() {
+                    queue!(stdout, SetForegroundColor(Color::DarkGrey), Print(name), ResetColor)?;
+                } else {
+                    queue!(stdout, Print(name))?;
+                }
+            }
+        }
+
+        // == STATUS BAR ==
+        let mut status = format!(
+            " {} @ {} | {} | {} online",
+            self.client.name(),
+            self.client.address(),
+            match (self.connected, self.reconnect) {
+                (true, _) => "connected",
+                (false, Some(_)) => "reconnecting",
+                (false, None) => "disconnected",
+            },
+            self.users.len()
+        );
+        let unacknowledged = self.client.pending().len();
+        if unacknowledged > 0 {
+            status.push_str(&format!(" | {} unacknowledged", unacknowledged));
+        }
+        if self.scroll > 0 {
+            status.push_str(&format!(" | scrolled up {}", self.scroll));
+        }
+        if let Some(typing) = self.typing.summary() {
+            status.push_str(&format!(" | {}", typing));
+        }
+        let status: String = format!("{:<width$}", status, width = width as usize).chars().take(width as usize).collect();
+        queue!(
+            stdout,
+            MoveTo(0, height - 2),
+            SetAttribute(Attribute::Reverse),
+            Print(status),
+            SetAttribute(Attribute::Reset)
+        )?;
+
+        // == INPUT LINE ==
+        let prompt = "> ";
+        let visible = (width as usize).saturating_sub(prompt.len() + 1).max(1);
+        let offset = self.cursor.saturating_sub(visible);
+        let input: String = self.input.iter().skip(offset).take(visible).collect();
+        queue!(
+            stdout,
+            MoveTo(0, height - 1),
+            Print(prompt),
+            Print(input),
+            MoveTo((prompt.len() + self.cursor - offset) as u16, height - 1)
+        )?;
+
+        stdout.flush()
+    }
+}
@@ -0,0 +1,341 @@
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use super::gate::Gate;
+use super::server::{read_request_line, RequestLine, ServerHandle};
+use super::tools::{metered_lock, Connection};
+use super::{commands, request_type, server_log, LogMessagType};
+
+/// Appended to the client's key before hashing, fixed by RFC 6455
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// Largest message a browser client may send, bigger ones close the connection
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+/// Longest line of the upgrade request, a longer one fails the handshake
+const MAX_HEADER_LENGTH: usize = 8 * 1024;
+
+/// Frame opcodes
+pub(crate) mod opcode {
+    pub const CONTINUATION: u8 = 0x0;
+    pub const TEXT: u8 = 0x1;
+    pub const CLOSE: u8 = 0x8;
+    pub const PING: u8 = 0x9;
+    pub const PONG: u8 = 0xA;
+}
+
+/// # `Message`
+/// A complete message from a WebSocket client
+enum Message {
+    Text(String),
+    Ping(Vec<u8>),
+    Close,
+}
+
+/// # `serve_websocket`
+/// Accepts WebSocket clients on `listener` from a background thread. Each one does the HTTP upgrade, then speaks the same
+/// line protocol as TCP clients with one or more lines per text frame, and joins through the same `Gate`
+pub(crate) fn serve_websocket(listener: TcpListener, gate: Gate, handle: ServerHandle) {
+    if let Ok(address) = listener.local_addr() {
+        server_log(format!("WebSocket gateway listening on {}", address), LogMessagType::Information);
+    }
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            if !handle.is_running() {
+                break;
+            }
+            if let Ok(stream) = stream {
+                let gate = gate.clone();
+                thread::spawn(move || handle_connection(stream, gate));
+            }
+        }
+    });
+}
+
+/// # `handle_connection`
+/// Upgrades one connection, lets it join and forwards its requests until it closes
+fn handle_connection(stream: TcpStream, gate: Gate) {
+    let address = match stream.peer_addr() {
+        Ok(address) => address,
+        Err(_) => return,
+    };
+    let (mut reader, mut writer) = match (stream.try_clone(), stream.try_clone()) {
+        (Ok(reader), Ok(writer)) => (BufReader::new(reader), writer),
+        _ => {
+            server_log(format!("Error cloning socket for client {}", address), LogMessagType::EncounteredError);
+            return;
+        }
+    };
+
+    if let Err(e) = handshake(&mut reader, &mut writer) {
+        server_log(format!("WebSocket handshake with {} failed \"{}\"", address, e), LogMessagType::Warning);
+        return;
+    }
+
+    // The first message has to be the connection request
+    let request = match read_message(&mut reader, &mut Vec::new()) {
+        Ok(Message::Text(request)) => request,
+        _ => return,
+    };
+    let name = match gate.admit(request.trim(), Some(address), Connection::WebSocket(stream.into())) {
+        Some(name) => name,
+        None => {
+            let _ = write_frame(&mut writer, opcode::CLOSE, &[]);
+            return;
+        }
+    };
+
+    // Built when it is needed, the name may have changed with /nick by then
+    let logout = || format!("{} {} {}", request_type::COMMAND, name.get(), commands::LOGOUT);
+    // Text of a fragmented message, it is kept while control frames arrive between its fragments
+    let mut partial = Vec::new();
+    loop {
+        match read_message(&mut reader, &mut partial) {
+            Ok(Message::Text(text)) => {
+                for request in text.lines().map(|line| line.trim_end()).filter(|line| !line.is_empty()) {
+                    if !gate.forward(&name, request) {
+                        server_log(format!("Error sending request from {} for handling", address), LogMessagType::EncounteredError);
+                    }
+                }
+            }
+            Ok(Message::Ping(payload)) => {
+                // Holding the client list keeps the pong from landing in the middle of a frame sent by another thread
+                if let Ok(_clients) = metered_lock(&gate.clients) {
+                    let _ = write_frame(&mut writer, opcode::PONG, &payload);
+                }
+            }
+            Ok(Message::Close) => {
+                let _ = gate.requests.send(logout());
+                if let Ok(_clients) = metered_lock(&gate.clients) {
+                    let _ = write_frame(&mut writer, opcode::CLOSE, &[]);
+                }
+                return;
+            }
+            Err(e) => {
+                server_log(format!("Error \"{}\" reading from client {}. Closing thread", e, address), LogMessagType::EncounteredError);
+                let _ = gate.requests.send(logout());
+                return;
+            }
+        }
+    }
+}
+
+/// # `handshake`
+/// Reads the HTTP upgrade request and answers it with `101 Switching Protocols`, or `400 Bad Request` if it is not a WebSocket upgrade
+fn handshake(reader: &mut impl BufRead, writer: &mut impl Write) -> io::Result<()> {
+    let mut upgrade = false;
+    let mut key = None;
+    loop {
+        let line = match read_request_line(reader, MAX_HEADER_LENGTH)? {
+            RequestLine::Line(line) => line,
+            RequestLine::TooLong(_) => return Err(io::Error::new(io::ErrorKind::InvalidData, "Header line too long")),
+            RequestLine::Closed => {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed during handshake"))
+            }
+        };
+        if line.is_empty() {
+            break;
+        }
+        if let Some((header, value)) = line.split_once(':') {
+            match header.trim().to_ascii_lowercase().as_str() {
+                "upgrade" => upgrade = value.trim().eq_ignore_ascii_case("websocket"),
+                "sec-websocket-key" => key = Some(value.trim().to_string()),
+                _ => {}
+            }
+        }
+    }
+
+    match key.filter(|_| upgrade) {
+        Some(key) => write!(
+            writer,
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+            accept_key(&key)
+        ),
+        None => {
+            write!(writer, "HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")?;
+            Err(io::Error::new(io::ErrorKind::InvalidData, "Not a WebSocket upgrade request"))
+        }
+    }
+}
+
+/// # `accept_key`
+/// The `Sec-WebSocket-Accept` answer to a `Sec-WebSocket-Key`
+fn accept_key(key: &str) -> String {
+    STANDARD.encode(sha1_smol::Sha1::from(format!("{}{}", key, ACCEPT_GUID)).digest().bytes())
+}
+
+/// # `read_message`
+/// Reads frames until a whole message is there. Fragmented text is put back together in `text`, pongs are skipped.
+/// Pings and closes may come between the fragments of a message (RFC 6455 5.4), they are returned right away and the
+/// fragments read so far stay in `text` for the next call
+fn read_message(reader: &mut impl Read, text: &mut Vec<u8>) -> io::Result<Message> {
+    loop {
+        let mut header = [0u8; 2];
+        reader.read_exact(&mut header)?;
+        let fin = header[0] & 0x80 != 0;
+        let code = header[0] & 0x0F;
+        let masked = header[1] & 0x80 != 0;
+        let length = match header[1] & 0x7F {
+            126 => {
+                let mut length = [0u8; 2];
+                reader.read_exact(&mut length)?;
+                u16::from_be_bytes(length) as usize
+            }
+            127 => {
+                let mut length = [0u8; 8];
+                reader.read_exact(&mut length)?;
+                u64::from_be_bytes(length) as usize
+            }
+            length => length as usize,
+        };
+        // Clients always mask their frames
+        if !masked {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Unmasked frame from client"));
+        }
+        if length > MAX_MESSAGE_SIZE - text.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Message too large"));
+        }
+
+        let mut mask = [0u8; 4];
+        reader.read_exact(&mut mask)?;
+        let mut payload = vec![0u8; length];
+        reader.read_exact(&mut payload)?;
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+
+        match code {
+            opcode::TEXT | opcode::CONTINUATION => {
+                text.extend_from_slice(&payload);
+                if fin {
+                    return String::from_utf8(std::mem::take(text))
+                        .map(Message::Text)
+                        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Text frame is not UTF-8"));
+                }
+            }
+            opcode::PING => return Ok(Message::Ping(payload)),
+            opcode::PONG => {}
+            opcode::CLOSE => return Ok(Message::Close),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Binary frames are not supported")),
+        }
+    }
+}
+
+/// # `write_frame`
+/// Writes one unmasked, unfragmented frame (servers never mask)
+pub(crate) fn write_frame(writer: &mut impl Write, code: u8, payload: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | code);
+    match payload.len() {
+        length if length < 126 => frame.push(length as u8),
+        length if length <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    writer.write_all(&frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// A frame the way a client sends it, masked
+    fn client_frame(fin: bool, code: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut frame = vec![if fin { 0x80 | code } else { code }];
+        match payload.len() {
+            length if length < 126 => frame.push(0x80 | length as u8),
+            length => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(length as u16).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+        frame
+    }
+
+    fn text(message: io::Result<Message>) -> String {
+        match message {
+            Ok(Message::Text(text)) => text,
+            _ => panic!("Expected a text message"),
+        }
+    }
+
+    #[test]
+    fn accept_key_matches_rfc_example() {
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn handshake_refuses_endless_header_lines() {
+        let mut request = b"GET / HTTP/1.1\r\nUpgrade: websocket\r\nX-Long: ".to_vec();
+        request.extend(vec![b'x'; MAX_HEADER_LENGTH * 4]);
+        let mut response = Vec::new();
+        let error = handshake(&mut request.as_slice(), &mut response).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(response.is_empty());
+    }
+
+    #[test]
+    fn reads_single_and_long_frames() {
+        let long = "a".repeat(300);
+        let mut bytes = client_frame(true, opcode::TEXT, b"MSG azeez hi");
+        bytes.extend(client_frame(true, opcode::TEXT, long.as_bytes()));
+        let mut reader = Cursor::new(bytes);
+        let mut partial = Vec::new();
+        assert_eq!(text(read_message(&mut reader, &mut partial)), "MSG azeez hi");
+        assert_eq!(text(read_message(&mut reader, &mut partial)), long);
+    }
+
+    #[test]
+    fn keeps_fragments_across_control_frames() {
+        let mut bytes = client_frame(false, opcode::TEXT, b"MSG azeez ");
+        bytes.extend(client_frame(true, opcode::PING, b"ping"));
+        bytes.extend(client_frame(true, opcode::PONG, b""));
+        bytes.extend(client_frame(false, opcode::CONTINUATION, b"hello "));
+        bytes.extend(client_frame(true, opcode::CONTINUATION, b"there"));
+        let mut reader = Cursor::new(bytes);
+        let mut partial = Vec::new();
+
+        match read_message(&mut reader, &mut partial) {
+            Ok(Message::Ping(payload)) => assert_eq!(payload, b"ping"),
+            _ => panic!("Expected the ping first"),
+        }
+        assert_eq!(text(read_message(&mut reader, &mut partial)), "MSG azeez hello there");
+        assert!(partial.is_empty());
+    }
+
+    #[test]
+    fn refuses_unmasked_binary_and_oversized_frames() {
+        let unmasked = vec![0x80 | opcode::TEXT, 2, b'h', b'i'];
+        assert!(read_message(&mut Cursor::new(unmasked), &mut Vec::new()).is_err());
+
+        let binary = client_frame(true, 0x2, b"hi");
+        assert!(read_message(&mut Cursor::new(binary), &mut Vec::new()).is_err());
+
+        let mut oversized = vec![0x80 | opcode::TEXT, 0x80 | 127];
+        oversized.extend_from_slice(&(MAX_MESSAGE_SIZE as u64 + 1).to_be_bytes());
+        assert!(read_message(&mut Cursor::new(oversized), &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn writes_frames_with_every_length_encoding() {
+        for (length, header) in [(125, 2), (126, 4), (u16::MAX as usize + 1, 10)] {
+            let mut frame = Vec::new();
+            write_frame(&mut frame, opcode::TEXT, &vec![b'a'; length]).unwrap();
+            assert_eq!(frame[0], 0x80 | opcode::TEXT);
+            assert_eq!(frame.len(), header + length);
+        }
+    }
+}
@@ -0,0 +1,32 @@
+mod common;
+
+use std::{net::TcpStream, sync::mpsc, thread, time::Duration};
+
+use azeezd_sockets::socket_chat::{send_admin_command, Server};
+use common::RawClient;
+
+#[test]
+fn shutdown_stops_the_server_without_exiting() {
+    let mut server = Server::builder().address("127.0.0.1:0").build().unwrap();
+    let socket = std::env::temp_dir().join(format!("azeezd-sockets-test-{}.sock", std::process::id()));
+    server.serve_admin(&socket).unwrap();
+    let handle = server.handle();
+    let (stopped, init_returned) = mpsc::channel();
+    thread::spawn(move || {
+        server.init();
+        let _ = stopped.send(());
+    });
+
+    // A connection that never sends its connection request does not hold anything up
+    let _silent = TcpStream::connect(handle.address().to_string()).unwrap();
+    let mut alice = RawClient::join(&handle, "alice");
+
+    assert_eq!(send_admin_command(&socket, "SHUTDOWN").unwrap(), vec!["OK".to_string()]);
+    init_returned.recv_timeout(Duration::from_secs(5)).expect("Server did not stop");
+    assert!(!handle.is_running());
+    assert_eq!(alice.expect("CDE"), "CDE");
+
+    // The admin socket goes away with the server
+    thread::sleep(Duration::from_millis(200));
+    assert!(!socket.exists());
+}
@@ -0,0 +1,97 @@
+//! Helpers shared by the integration tests: a server on a free port and raw protocol clients
+
+#![allow(dead_code)]
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+    time::Duration,
+};
+
+use azeezd_sockets::socket_chat::{Server, ServerBuilder, ServerHandle};
+
+/// How long a test waits for a line before giving up
+pub const READ_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// # `start`
+/// Builds the server on a free port of localhost and runs it on its own thread
+pub fn start(builder: ServerBuilder) -> ServerHandle {
+    let mut server = builder.address("127.0.0.1:0").build().expect("Error building server");
+    let handle = server.handle();
+    thread::spawn(move || server.init());
+    handle
+}
+
+/// # `start_default`
+/// A server with the default commands and config
+pub fn start_default() -> ServerHandle {
+    start(Server::builder())
+}
+
+/// # `free_address`
+/// A localhost address with a port nobody listens on, for listeners whose address the server does not report
+pub fn free_address() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Error finding a free port");
+    listener.local_addr().unwrap().to_string()
+}
+
+/// # `RawClient`
+/// A client speaking the line protocol by hand, so tests can send requests the real client never would
+pub struct RawClient {
+    writer: TcpStream,
+    reader: BufReader<TcpStream>,
+}
+
+impl RawClient {
+    /// # `join`
+    /// Connects to `handle`'s server and joins as `name`, panics if the server denies it
+    pub fn join(handle: &ServerHandle, name: &str) -> RawClient {
+        let mut client = RawClient::connect(&handle.address().to_string());
+        client.send(&format!("CON {}", name));
+        assert_eq!(client.line().as_deref(), Some("CAC"), "{} was not accepted", name);
+        client
+    }
+
+    /// # `connect`
+    /// Opens a connection to `address` without joining
+    pub fn connect(address: &str) -> RawClient {
+        let writer = TcpStream::connect(address).expect("Error connecting to server");
+        writer.set_read_timeout(Some(READ_TIMEOUT)).unwrap();
+        let reader = BufReader::new(writer.try_clone().unwrap());
+        RawClient { writer, reader }
+    }
+
+    /// # `send`
+    /// Writes one request line
+    pub fn send(&mut self, line: &str) {
+        self.send_bytes(format!("{}\n", line).as_bytes());
+    }
+
+    /// # `send_bytes`
+    /// Writes raw bytes, for requests that are not valid UTF-8
+    pub fn send_bytes(&mut self, bytes: &[u8]) {
+        self.writer.write_all(bytes).expect("Error writing to server");
+    }
+
+    /// # `line`
+    /// The next response line, `None` if nothing came in time or the connection closed
+    pub fn line(&mut self) -> Option<String> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line.trim_end().to_string()),
+        }
+    }
+
+    /// # `expect`
+    /// Skips response lines until one starts with `prefix` and returns it, panics if none comes in time
+    pub fn expect(&mut self, prefix: &str) -> String {
+        while let Some(line) = self.line() {
+            if line.starts_with(prefix) {
+                return line;
+            }
+        }
+        panic!("No response starting with \"{}\"", prefix);
+    }
+}
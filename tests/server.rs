@@ -0,0 +1,165 @@
+mod common;
+
+use azeezd_sockets::socket_chat::{ApiToken, CommandRegistry, Config, Server};
+use common::{start, start_default, RawClient};
+
+#[test]
+fn request_in_another_name_is_rejected() {
+    let handle = start_default();
+    let mut alice = RawClient::join(&handle, "alice");
+    let mut bob = RawClient::join(&handle, "bob");
+    alice.expect("UJS bob");
+
+    alice.send("@1 MSG alice hello");
+    let message = bob.expect("MSG");
+    let id = message.split(' ').nth(1).unwrap().to_string();
+
+    // Bob writes in alice's name: a message, a delete of her message and a logout
+    bob.send("@7 MSG alice i am alice");
+    assert!(bob.expect("NAK 7").ends_with("Requests can only be sent in your own name"));
+    bob.send(&format!("@8 CMD alice /delete {}", id));
+    bob.expect("NAK 8");
+    bob.send("@9 CMD alice /exit");
+    bob.expect("NAK 9");
+    bob.send("CMD alice /exit");
+    bob.expect("ERR");
+
+    // None of it happened, alice is still there and her message is too
+    alice.send("@2 CMD alice /who");
+    assert_eq!(alice.expect("ULI"), "ULI alice bob");
+    alice.send(&format!("@3 CMD alice /edit {} hello again", id));
+    alice.expect("MED");
+    handle.stop();
+}
+
+#[test]
+fn messages_stay_with_their_sender_after_a_rename() {
+    let handle = start_default();
+    let mut alice = RawClient::join(&handle, "alice");
+    alice.send("@1 MSG alice hello");
+    let id = alice.expect("MSG").split(' ').nth(1).unwrap().to_string();
+    alice.send("@2 CMD alice /nick alicia");
+    alice.expect("ACK 2");
+
+    // Someone taking the old name does not get the message with it
+    let mut newcomer = RawClient::join(&handle, "alice");
+    newcomer.send(&format!("@9 CMD alice /edit {} hijacked", id));
+    assert!(newcomer.expect("NAK 9").ends_with("You can only change your own messages"));
+
+    alice.send(&format!("@3 CMD alicia /edit {} hello again", id));
+    assert_eq!(alice.expect("MED"), format!("MED {} alice hello again", id));
+    handle.stop();
+}
+
+#[test]
+fn banned_and_operator_names_can_not_be_taken_with_nick() {
+    let config = Config {
+        banned_names: vec!["troll".to_string()],
+        operators: vec!["admin".to_string()],
+        ..Config::default()
+    };
+    let handle = start(Server::builder().config(config));
+    let mut bob = RawClient::join(&handle, "bob");
+    bob.send("@1 CMD bob /nick troll");
+    assert_eq!(bob.expect("NAK 1"), "NAK 1 troll is banned");
+    bob.send("@2 CMD bob /nick admin");
+    assert_eq!(bob.expect("NAK 2"), "NAK 2 admin is reserved for an operator");
+    bob.send("@3 CMD bob /announce hi");
+    bob.expect("NAK 3");
+    handle.stop();
+}
+
+#[test]
+fn requests_that_are_not_utf8_are_read_lossily() {
+    let handle = start_default();
+    let mut alice = RawClient::join(&handle, "alice");
+    alice.send_bytes(b"@1 MSG alice caf\xe9\n");
+    assert!(alice.expect("MSG").ends_with("alice caf\u{fffd}"));
+    alice.expect("ACK 1");
+
+    // Still connected
+    alice.send("@2 CMD alice /who");
+    assert_eq!(alice.expect("ULI"), "ULI alice");
+    handle.stop();
+}
+
+#[test]
+fn clients_leave_whatever_the_registry_holds() {
+    let handle = start(Server::builder().commands(CommandRegistry::empty()));
+    let mut alice = RawClient::join(&handle, "alice");
+    let mut bob = RawClient::join(&handle, "bob");
+    alice.expect("UJS bob");
+
+    // Logging out and losing the connection both free the name
+    bob.send("CMD bob /exit");
+    alice.expect("ULS bob");
+    let mut bob = RawClient::join(&handle, "bob");
+    alice.expect("UJS bob");
+    drop(alice);
+    bob.expect("ULS alice");
+    RawClient::join(&handle, "alice");
+    handle.stop();
+}
+
+#[test]
+fn operator_names_need_the_operator_password() {
+    let config = Config {
+        operators: vec!["admin".to_string()],
+        operator_password: Some("secret".to_string()),
+        ..Config::default()
+    };
+    let handle = start(Server::builder().config(config));
+    for request in ["CON admin", "CON admin wrong"] {
+        let mut client = RawClient::connect(&handle.address().to_string());
+        client.send(request);
+        assert_eq!(client.line().as_deref(), Some("CDE"), "{}", request);
+    }
+
+    let mut admin = RawClient::connect(&handle.address().to_string());
+    admin.send("CON admin secret");
+    assert_eq!(admin.line().as_deref(), Some("CAC"));
+    admin.send("@1 CMD admin /announce hello");
+    assert_eq!(admin.expect("SYS"), "SYS hello");
+    admin.expect("ACK 1");
+
+    // Going by another name ends the operator commands
+    admin.send("@2 CMD admin /nick plain");
+    admin.expect("ACK 2");
+    admin.send("@3 CMD plain /announce hello");
+    admin.expect("NAK 3");
+    handle.stop();
+}
+
+#[test]
+fn api_token_names_are_not_for_clients() {
+    let config = Config {
+        api_tokens: vec![ApiToken { name: "ci".to_string(), token: "secret".to_string() }],
+        ..Config::default()
+    };
+    let handle = start(Server::builder().config(config));
+    let mut client = RawClient::connect(&handle.address().to_string());
+    client.send("CON ci");
+    assert_eq!(client.line().as_deref(), Some("CDE"));
+
+    let mut bob = RawClient::join(&handle, "bob");
+    bob.send("@1 CMD bob /nick ci");
+    assert_eq!(bob.expect("NAK 1"), "NAK 1 ci is reserved for the HTTP API");
+    handle.stop();
+}
+
+#[test]
+fn endless_lines_are_rejected_while_reading() {
+    // No limit set, the default one applies
+    let handle = start_default();
+    let mut alice = RawClient::join(&handle, "alice");
+    let mut line = b"@1 MSG alice ".to_vec();
+    line.extend(vec![b'x'; 1024 * 1024]);
+    line.push(b'\n');
+    alice.send_bytes(&line);
+    assert!(alice.expect("NAK 1").contains("Message is too long"));
+
+    // The rest of the line was skipped, the connection goes on
+    alice.send("@2 CMD alice /who");
+    assert_eq!(alice.expect("ULI"), "ULI alice");
+    handle.stop();
+}
@@ -0,0 +1,115 @@
+mod common;
+
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+};
+
+use azeezd_sockets::socket_chat::Server;
+use common::{free_address, start, RawClient, READ_TIMEOUT};
+
+const TEXT: u8 = 0x1;
+const CONTINUATION: u8 = 0x0;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xA;
+
+/// # `WebSocketClient`
+/// Just enough of a browser to talk to the gateway: the upgrade, masked frames out and unmasked frames in
+struct WebSocketClient {
+    stream: TcpStream,
+}
+
+impl WebSocketClient {
+    fn connect(address: &str) -> WebSocketClient {
+        let mut stream = TcpStream::connect(address).expect("Error connecting to the gateway");
+        stream.set_read_timeout(Some(READ_TIMEOUT)).unwrap();
+        write!(
+            stream,
+            "GET /chat HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            address
+        )
+        .unwrap();
+
+        // Read byte by byte up to the empty line, so no frame after the headers gets buffered away
+        let mut head = Vec::new();
+        let mut byte = [0u8];
+        while !head.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).expect("Connection closed during the upgrade");
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8(head).unwrap();
+        assert!(head.starts_with("HTTP/1.1 101"), "Upgrade refused: {}", head);
+        let accept = head.lines().find_map(|line| line.strip_prefix("Sec-WebSocket-Accept: "));
+        assert_eq!(accept, Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+        WebSocketClient { stream }
+    }
+
+    fn send_frame(&mut self, fin: bool, code: u8, payload: &[u8]) {
+        let mask = [0xA1, 0xB2, 0xC3, 0xD4];
+        let mut frame = vec![if fin { 0x80 | code } else { code }, 0x80 | payload.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+        self.stream.write_all(&frame).unwrap();
+    }
+
+    fn send_text(&mut self, text: &str) {
+        self.send_frame(true, TEXT, text.as_bytes());
+    }
+
+    /// The next frame from the server as its opcode and payload
+    fn frame(&mut self) -> (u8, Vec<u8>) {
+        let mut header = [0u8; 2];
+        self.stream.read_exact(&mut header).expect("No frame from the gateway");
+        let length = match header[1] & 0x7F {
+            126 => {
+                let mut length = [0u8; 2];
+                self.stream.read_exact(&mut length).unwrap();
+                u16::from_be_bytes(length) as usize
+            }
+            length => length as usize,
+        };
+        let mut payload = vec![0u8; length];
+        self.stream.read_exact(&mut payload).unwrap();
+        (header[0] & 0x0F, payload)
+    }
+
+    /// Skips text frames until one starts with `prefix`
+    fn expect(&mut self, prefix: &str) -> String {
+        loop {
+            let (code, payload) = self.frame();
+            let text = String::from_utf8(payload).unwrap();
+            if code == TEXT && text.starts_with(prefix) {
+                return text;
+            }
+        }
+    }
+}
+
+#[test]
+fn websocket_clients_chat_with_single_and_fragmented_frames() {
+    let gateway = free_address();
+    let handle = start(Server::builder().websocket(&gateway));
+    let mut alice = RawClient::join(&handle, "alice");
+
+    let mut browser = WebSocketClient::connect(&gateway);
+    browser.send_text("CON web");
+    assert_eq!(browser.expect("CAC"), "CAC");
+    alice.expect("UJS web");
+
+    browser.send_text("@1 MSG web hello from the browser");
+    assert!(alice.expect("MSG").ends_with("web hello from the browser"));
+    browser.expect("ACK 1");
+
+    // A ping between the fragments is answered and the message still arrives whole
+    browser.send_frame(false, TEXT, b"@2 MSG web in ");
+    browser.send_frame(true, PING, b"are you there");
+    assert_eq!(browser.frame(), (PONG, b"are you there".to_vec()));
+    browser.send_frame(false, CONTINUATION, b"three ");
+    browser.send_frame(true, CONTINUATION, b"pieces");
+    assert!(alice.expect("MSG").ends_with("web in three pieces"));
+    browser.expect("ACK 2");
+
+    alice.send("@1 MSG alice hi web");
+    assert!(browser.expect("MSG").ends_with("alice hi web"));
+    handle.stop();
+}